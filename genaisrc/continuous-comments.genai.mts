@@ -7,6 +7,12 @@ import { classify } from "./src/classify.mts";
 import { cOps } from "./src/c.mts";
 import { cppOps } from "./src/cpp.mts";
 import { csharpOps } from "./src/csharp.mts";
+import {
+  getChangedLines,
+  isChanged,
+  isCommitRef,
+  type LineRange,
+} from "./src/gitdiff.mts";
 import { goOps } from "./src/go.mts";
 import { javaOps } from "./src/java.mts";
 import type { EntityKind, LanguageOps } from "./src/langops.mts";
@@ -83,6 +89,60 @@ You should pretify your code before and after running this script to normalize t
       description: "Maximum number of tokens to build content of requests.",
       default: 6000,
     },
+    since: {
+      type: "string",
+      description: `Git ref (branch, tag or commit). If set, only process the entities whose lines changed since this ref, according to git diff.`,
+      default: "",
+    },
+    rustTraitImpls: {
+      type: "boolean",
+      description: `If true, document items of Rust trait impls (impl Trait for Type).
+      By default they are skipped since rustdoc inherits their docs from the trait.`,
+      default: false,
+    },
+    rustCrateVisible: {
+      type: "boolean",
+      description: `If true, exportsOnly also treats Rust pub(crate) items as exported.`,
+      default: false,
+    },
+    rustInclude: {
+      type: "string",
+      description: `Comma-separated list of Rust items that are skipped by default and should be documented.
+      Valid values: impl (impl blocks), main (main function), tests (#[test] and #[bench] functions and #[cfg(test)] items)`,
+      default: "",
+    },
+    rustSkipAttributes: {
+      type: "string",
+      description: `Semicolon-separated list of Rust attributes whose items, and everything they contain, are not documented, in addition to test, bench and cfg(test) unless rustInclude has tests.
+      Whitespace and path prefixes are ignored, e.g. "test" also matches #[tokio::test].`,
+      default: "doc(hidden)",
+    },
+    rustExamples: {
+      type: "boolean",
+      description: `If true, generated Rust docs include an # Examples section with a doctest.`,
+      default: false,
+    },
+    rustVerifyDoctests: {
+      type: "boolean",
+      description: `If true, run cargo test --doc (offline) on a scratch copy of the crate and drop generated Rust docs whose doctests fail. Requires cargo, which the action image does not include.`,
+      default: false,
+    },
+    rustMissingDocs: {
+      type: "boolean",
+      description: `If true, target exactly the Rust items flagged by the missing_docs lint: the public API of the crate, including fields, variants and trait items, without impl blocks, trait impl items or #[doc(hidden)] items.
+      Overrides kinds, exportsOnly, rustTraitImpls, rustCrateVisible and the impl value of rustInclude for Rust files.`,
+      default: false,
+    },
+    rustDocCfg: {
+      type: "boolean",
+      description: `If true, add #[cfg_attr(docsrs, doc(cfg(...)))] to the feature gated Rust items that get docs, in crates already using that convention.`,
+      default: false,
+    },
+    rustStaleCheck: {
+      type: "boolean",
+      description: `If true, only update the docs of Rust functions whose parameters, return type or error type no longer match what the docs mention.`,
+      default: true,
+    },
   },
 });
 const { output, dbg, vars } = env;
@@ -98,9 +158,19 @@ const {
   maxEdits,
   instructions,
   maxContext,
+  since,
   kinds,
   exportsOnly,
   judge,
+  rustTraitImpls,
+  rustInclude,
+  rustSkipAttributes,
+  rustCrateVisible,
+  rustExamples,
+  rustVerifyDoctests,
+  rustDocCfg,
+  rustStaleCheck,
+  rustMissingDocs,
 } = vars;
 const applyEdits = !dryRun;
 
@@ -115,14 +185,26 @@ dbg({
   maxEdits,
   instructions,
   maxContext,
+  since,
   kinds,
   exportsOnly,
   judge,
+  rustTraitImpls,
+  rustInclude,
+  rustSkipAttributes,
+  rustCrateVisible,
+  rustExamples,
+  rustVerifyDoctests,
+  rustDocCfg,
+  rustStaleCheck,
+  rustMissingDocs,
 });
 
 if (!addMissing && !updateExisting)
   cancel(`not generating or updating docs, exiting...`);
 if (!files.length) cancel(`no files to process, exiting...`);
+if (since && !(await isCommitRef(since)))
+  cancel(`unknown git ref ${since}, exiting...`);
 
 const entityKinds: EntityKind[] = kinds
   .split(",")
@@ -131,9 +213,35 @@ const entityKinds: EntityKind[] = kinds
 
 dbg(`entityKinds: %o`, entityKinds);
 
+const rustIncluded: string[] = (rustInclude || "")
+  .split(",")
+  .map((e: string) => e.trim())
+  .filter((e: string) => e);
+rustOps.configure({
+  traitImplItems: rustTraitImpls,
+  implBlocks: rustIncluded.includes("impl"),
+  main: rustIncluded.includes("main"),
+  tests: rustIncluded.includes("tests"),
+  skipAttributes: (rustSkipAttributes || "")
+    .split(";")
+    .map((e: string) => e.trim())
+    .filter((e: string) => e),
+  crateVisible: rustCrateVisible,
+  examples: rustExamples,
+  verifyDoctests: rustVerifyDoctests,
+  docCfg: rustDocCfg,
+  staleCheck: rustStaleCheck,
+  missingDocs: rustMissingDocs,
+});
+
 // launch ast-grep instance
 const sg = await astGrep();
 
+// load project metadata (e.g. Cargo.toml) for languages that support it
+const filenames = files.map((f) => f.filename);
+const languagesOps = new Set(files.map((f) => getLanguageOps(getLanguage(f))));
+for (const langOps of languagesOps) await langOps.loadProject?.(sg, filenames);
+
 // collect tokens, generation stats for final report
 type FileStats = {
   filename: string;
@@ -146,6 +254,8 @@ type FileStats = {
   updated: number; // # updated docs
   nits: number; // nits found, only for new docs
   refused: number; // refused generation
+  failedDoctests: number; // docs dropped by verifyDocs
+  skipped: number; // up to date docs, not sent to the LLM
 };
 const stats: FileStats[] = [];
 
@@ -191,6 +301,13 @@ for (const file of files) {
   }
   console.debug(file.filename);
 
+  // only process the lines changed since the ref, if any
+  let changes = since ? await getChangedLines(since, file.filename) : undefined;
+  if (changes?.length === 0) {
+    dbg(`no changes since %s, skipping`, since);
+    continue;
+  }
+
   // generate updated docs
   if (updateExisting) {
     stats.push({
@@ -204,8 +321,10 @@ for (const file of files) {
       updated: 0,
       nits: 0,
       refused: 0,
+      failedDoctests: 0,
+      skipped: 0,
     });
-    await updateDocs(file, stats.at(-1));
+    await updateDocs(file, stats.at(-1), changes);
   }
 
   // generate missing docs
@@ -221,8 +340,13 @@ for (const file of files) {
       updated: 0,
       nits: 0,
       refused: 0,
+      failedDoctests: 0,
+      skipped: 0,
     });
-    await addMissingDocs(file, stats.at(-1));
+    // written updates move the lines around
+    if (since && updateExisting && applyEdits)
+      changes = await getChangedLines(since, file.filename);
+    await addMissingDocs(file, stats.at(-1), changes);
   }
 }
 
@@ -243,12 +367,18 @@ if (stats.length) {
       updated: row.updated.toFixed(0),
       nits: row.nits?.toFixed(0) || "N/A",
       refused: row.refused.toFixed(0),
+      failedDoctests: row.failedDoctests.toFixed(0),
+      skipped: row.skipped.toFixed(0),
     }));
 
   output.table(table);
 }
 
-async function addMissingDocs(file: WorkspaceFile, fileStats: FileStats) {
+async function addMissingDocs(
+  file: WorkspaceFile,
+  fileStats: FileStats,
+  changes?: LineRange[]
+) {
   const language = getLanguage(file);
   const langOps = getLanguageOps(language);
   const rule = langOps.getCommentableNodesMatcher(
@@ -257,11 +387,20 @@ async function addMissingDocs(file: WorkspaceFile, fileStats: FileStats) {
     exportsOnly
   );
   dbg(`searching for missing docs in %s`, file.filename);
-  const { matches } = await sg.search(language, file.filename, { rule }, {});
+  let { matches } = await sg.search(language, file.filename, { rule }, {});
+  if (langOps.prioritizeMatches)
+    matches = langOps.prioritizeMatches(matches, file.filename, exportsOnly);
+  if (langOps.isIgnored)
+    matches = matches.filter((m) => !langOps.isIgnored(m));
+  if (changes) matches = matches.filter((m) => isChanged(m, changes));
   dbg(`found ${matches.length} missing docs`);
 
-  // build a changeset to accumate edits
-  const edits = sg.changeset();
+  // several entities can share an insertion node (e.g. a file header and
+  // the first item), so collect the docs per node before editing
+  const insertions = new Map<
+    string,
+    { node: SgNode; docs: string; count: number }
+  >();
 
   // for each match, generate a docstring for declarations not documented
   for (const match of matches) {
@@ -278,7 +417,14 @@ async function addMissingDocs(file: WorkspaceFile, fileStats: FileStats) {
             });
             const declRef = _.def("DECLARATION", declText, { flex: 10 });
             langOps
-              .addGenerateDocPrompt(_, declKind, declRef, fileRef)
+              .addGenerateDocPrompt(
+                _,
+                declKind,
+                declRef,
+                fileRef,
+                declNode,
+                file.filename
+              )
               .role("system");
             if (instructions) _.$`${instructions}`.role("system");
           },
@@ -302,7 +448,12 @@ async function addMissingDocs(file: WorkspaceFile, fileStats: FileStats) {
     const nodeToAdjust = getFirstNode(nodeToAdjust0);
     dbg(`node to adjust: %o`, nodeToAdjust.range());
 
-    const docs = getIndentedCommentText(res.text.trim(), nodeToAdjust, langOps);
+    const docs = getIndentedCommentText(
+      res.text.trim(),
+      nodeToAdjust,
+      langOps,
+      match
+    );
 
     // sanity check
     const judgeRes =
@@ -312,6 +463,7 @@ async function addMissingDocs(file: WorkspaceFile, fileStats: FileStats) {
             (_) => {
               _.def("FUNCTION", match.text());
               _.def("DOCS", docs);
+              langOps.addJudgePrompt?.(_, match, "<DOCS>");
             },
             {
               ok: "The content in <DOCS> is an accurate documentation for the code in <FUNCTION>.",
@@ -338,17 +490,48 @@ async function addMissingDocs(file: WorkspaceFile, fileStats: FileStats) {
       output.fence(judgeRes.answer);
       continue;
     }
-    edits.replace(nodeToAdjust, `${docs}${nodeToAdjust.text()}`);
+    const { start, end } = nodeToAdjust.range();
+    const key = `${start.index}:${end.index}`;
+    const insertion = insertions.get(key);
+    if (insertion) {
+      insertion.docs += docs;
+      insertion.count++;
+    } else insertions.set(key, { node: nodeToAdjust, docs, count: 1 });
     fileStats.generated++;
     onUpdate();
   }
 
-  // apply all edits and write to the file
-  const modifiedFiles = edits.commit();
+  // apply all edits, in file order so verification can locate the docs
+  const entries = [...insertions.values()].sort(
+    (a, b) => a.node.range().start.index - b.node.range().start.index
+  );
+  const commit = (entries: { node: SgNode; docs: string }[]) => {
+    const edits = sg.changeset();
+    for (const { node, docs } of entries)
+      edits.replace(node, `${docs}${node.text()}`);
+    return edits.commit();
+  };
+  let modifiedFiles = commit(entries);
   if (!modifiedFiles?.length) {
     dbg("no edits to apply");
     return;
   }
+  if (langOps.verifyDocs) {
+    const accepted = await langOps.verifyDocs(
+      file.filename,
+      modifiedFiles[0].content,
+      entries.map((e) => e.docs)
+    );
+    const rejected = entries.filter((_, i) => !accepted[i]);
+    if (rejected.length) {
+      const count = rejected.reduce((n, e) => n + e.count, 0);
+      output.warn(`dropping ${count} generated docs with failing doctests`);
+      fileStats.failedDoctests += count;
+      fileStats.generated -= count;
+      modifiedFiles = commit(entries.filter((_, i) => accepted[i]));
+      if (!modifiedFiles?.length) return;
+    }
+  }
   if (applyEdits) {
     await workspace.writeFiles(modifiedFiles);
   }
@@ -366,7 +549,11 @@ function getFirstNode(node: SgNode) {
   return node;
 }
 
-async function updateDocs(file: WorkspaceFile, fileStats: FileStats) {
+async function updateDocs(
+  file: WorkspaceFile,
+  fileStats: FileStats,
+  changes?: LineRange[]
+) {
   const language = getLanguage(file);
   const langOps = getLanguageOps(language);
   const rule = langOps.getCommentableNodesMatcher(
@@ -374,13 +561,27 @@ async function updateDocs(file: WorkspaceFile, fileStats: FileStats) {
     true,
     exportsOnly
   );
-  const { matches } = await sg.search(language, file.filename, { rule }, {});
+  let { matches } = await sg.search(language, file.filename, { rule }, {});
+  if (langOps.prioritizeMatches)
+    matches = langOps.prioritizeMatches(matches, file.filename, exportsOnly);
+  if (langOps.isIgnored)
+    matches = matches.filter((m) => !langOps.isIgnored(m));
+  if (changes) matches = matches.filter((m) => isChanged(m, changes));
   dbg(`found ${matches.length} docs to updateExisting`);
-  const edits = sg.changeset();
+  const updates: { docNodes: SgNode[]; docs: string }[] = [];
   // for each match, generate a docstring for functions not documented
   for (const match of matches) {
     if (shouldStop()) break;
     const docNodes = langOps.getCommentNodes(match);
+    if (!docNodes?.length) {
+      dbg(`no editable docs found, skipping`);
+      continue;
+    }
+    if (langOps.isDocStale?.(match, docNodes) === false) {
+      dbg(`docs match the declaration, skipping`);
+      fileStats.skipped++;
+      continue;
+    }
     let { declNode, declKind } = getDeclNodeAndKind(match);
     const declText = declNode ? declNode.text() : match.text();
 
@@ -396,7 +597,9 @@ async function updateDocs(file: WorkspaceFile, fileStats: FileStats) {
             const declRef = _.def("DECLARATION", declText, { flex: 10 });
             _.def("FILE", match.getRoot().root().text(), { flex: 1 });
             _.def("DOCSTRING", docsText, { flex: 10 });
-            langOps.addUpdateDocPrompt(_, declKind, declRef).role("system");
+            langOps
+              .addUpdateDocPrompt(_, declKind, declRef, declNode, file.filename)
+              .role("system");
           },
           {
             model,
@@ -425,7 +628,8 @@ async function updateDocs(file: WorkspaceFile, fileStats: FileStats) {
     const newDocs = getIndentedCommentText(
       res.text.trim(),
       docNodes[0],
-      langOps
+      langOps,
+      match
     );
 
     // Ask LLM if change is worth it
@@ -438,6 +642,7 @@ async function updateDocs(file: WorkspaceFile, fileStats: FileStats) {
               _.def("ORIGINAL_DOCS", docsText);
               _.def("NEW_DOCS", newDocs);
               _.$`An LLM generated an updated docstring <NEW_DOCS> for ${declKind} ${declRef}. The original docstring is <ORIGINAL_DOCS>.`;
+              langOps.addJudgePrompt?.(_, match, "<NEW_DOCS>");
             },
             {
               APPLY:
@@ -464,37 +669,51 @@ async function updateDocs(file: WorkspaceFile, fileStats: FileStats) {
       fileStats.nits++;
       continue;
     }
-    edits.replace(docNodes[0], newDocs.trimEnd());
-
-    // For additional comment nodes in multi-line comments,
-    // remove them by replacing with empty string and handle line cleanup in post-processing
-    for (let i = 1; i < docNodes.length; i++) {
-      edits.replace(docNodes[i], "");
-    }
+    updates.push({ docNodes, docs: newDocs.trimEnd() });
     fileStats.updated++;
     onUpdate();
   }
 
-  // apply all edits and write to the file
-  const modifiedFiles = edits.commit();
+  // apply all edits, in file order so verification can locate the docs
+  updates.sort(
+    (a, b) =>
+      a.docNodes[0].range().start.index - b.docNodes[0].range().start.index
+  );
+  // splice the old doc comments only, leaving the rest of the file as is
+  const commit = (
+    updates: { docNodes: SgNode[]; docs: string }[]
+  ): WorkspaceFile[] => {
+    if (!updates.length) return [];
+    const text = updates[0].docNodes[0].getRoot().root().text();
+    const content = spliceText(
+      text,
+      updates.flatMap(({ docNodes, docs }) =>
+        getDocSplices(text, docNodes, docs)
+      )
+    );
+    return content === text ? [] : [{ filename: file.filename, content }];
+  };
+  let modifiedFiles = commit(updates);
   if (!modifiedFiles?.length) {
     dbg("no edits to apply");
     return;
   }
-
-  // Post-process to remove blank lines left by comment removal
-  if (modifiedFiles.length > 0) {
-    const modifiedFile = modifiedFiles[0];
-    const cleanedContent = modifiedFile.content
-      // Remove lines that only contain whitespace
-      .replace(/^[ \t]*\r?\n/gm, "")
-      // But preserve intentional blank lines by allowing max 1 consecutive blank line
-      .replace(/\n\n\n+/g, "\n\n");
-
-    modifiedFiles[0] = {
-      ...modifiedFile,
-      content: cleanedContent,
-    };
+  if (langOps.verifyDocs) {
+    const accepted = await langOps.verifyDocs(
+      file.filename,
+      modifiedFiles[0].content,
+      updates.map((u) => u.docs)
+    );
+    const rejected = updates.filter((_, i) => !accepted[i]);
+    if (rejected.length) {
+      output.warn(
+        `dropping ${rejected.length} updated docs with failing doctests`
+      );
+      fileStats.failedDoctests += rejected.length;
+      fileStats.updated -= rejected.length;
+      modifiedFiles = commit(updates.filter((_, i) => accepted[i]));
+      if (!modifiedFiles?.length) return;
+    }
   }
 
   if (applyEdits) {
@@ -507,10 +726,54 @@ async function updateDocs(file: WorkspaceFile, fileStats: FileStats) {
   );
 }
 
+type Splice = { start: number; end: number; text: string };
+
+/**
+ * Computes the edits replacing a doc comment made of several nodes: the first
+ * run of contiguous nodes is replaced by the new docs, and the lines of the
+ * other runs (separated by attributes or regular comments) are removed.
+ * Nothing outside of the doc nodes is changed.
+ */
+function getDocSplices(
+  text: string,
+  docNodes: SgNode[],
+  docs: string
+): Splice[] {
+  const runs: { start: number; end: number }[] = [];
+  for (const node of docNodes) {
+    const { start, end } = node.range();
+    const last = runs.at(-1);
+    if (last && !text.slice(last.end, start.index).trim()) last.end = end.index;
+    else runs.push({ start: start.index, end: end.index });
+  }
+  return runs.map(({ start, end }, i) => {
+    // keep the line break some grammars include in comment nodes
+    const trailing = /\s*$/.exec(text.slice(start, end))[0];
+    if (i === 0) return { start, end, text: docs + trailing };
+    // remove the whole line when the run is alone on it
+    const lineStart = text.lastIndexOf("\n", start - 1) + 1;
+    if (text.slice(lineStart, start).trim()) return { start, end, text: "" };
+    const eol = trailing.includes("\n")
+      ? ""
+      : /^[ \t]*(\r?\n|$)/.exec(text.slice(end))?.[0] || "";
+    return { start: lineStart, end: end + eol.length, text: "" };
+  });
+}
+
+/**
+ * Applies non-overlapping splices to a text.
+ */
+function spliceText(text: string, splices: Splice[]): string {
+  return [...splices]
+    .sort((a, b) => b.start - a.start)
+    .reduce((t, s) => t.slice(0, s.start) + s.text + t.slice(s.end), text);
+}
+
 function getIndentedCommentText(
   docs: string,
   node: SgNode,
-  langOps: LanguageOps
+  langOps: LanguageOps,
+  decl: SgNode
 ): string {
   const range = node.range();
   dbg(`node range: %o`, range);
@@ -518,13 +781,17 @@ function getIndentedCommentText(
   dbg(`indentation: %s`, indentation);
 
   // TODO: Consider using a schema to restrict docs generation
-  docs = langOps.getCommentText(docs);
+  docs = langOps.getCommentText(docs, decl);
 
-  // normalize indentation
-  docs = docs.replace(/\r?\n/g, (m) => m + indentation);
+  // keep a trailing blank line separating the comment from the code
+  const separator = /\r?\n\r?\n$/.test(docs) ? "\n" : "";
 
   // remove trailing newlines
-  docs = docs.replace(/(\r?\n)+$/, "") + "\n" + indentation;
+  docs = docs.replace(/(\r?\n)+$/, "");
+
+  // normalize indentation
+  docs = docs.replace(/\r?\n/g, (m) => m + indentation);
+  docs = docs + "\n" + separator + indentation;
   dbg(`docified docs: <<<%s>>>`, docs);
 
   return docs;
@@ -0,0 +1,555 @@
+import { type SgNode } from "@genaiscript/plugin-ast-grep";
+import type { AstGrep } from "./langops.mts";
+import {
+  getImplTypeName,
+  hasOuterDocs,
+  isMacroExported,
+} from "./rustsyntax.mts";
+
+const dbg = host.logger("script:cargo");
+
+/**
+ * A crate described by a `Cargo.toml` manifest.
+ */
+export interface CargoCrate {
+  /** Crate name as used in paths, e.g. `my_crate` for package `my-crate` */
+  name: string;
+  /** Directory containing the manifest */
+  dir: string;
+  /** Crate root file: the lib target, or the main binary if there is no lib */
+  root: string;
+  /** True if the crate has a lib target */
+  lib: boolean;
+  /** Paths of the items declared in the crate, e.g. `geo::Point::new` */
+  symbols: Set<string>;
+  /** True if the crate uses `#[cfg_attr(docsrs, ...)]` attributes for docs.rs */
+  docsrs: boolean;
+  /** Names of the dependencies in the extern prelude, e.g. `serde_json` */
+  dependencies: Set<string>;
+}
+
+/**
+ * A module of a crate, backed by a source file.
+ */
+export interface RustModule {
+  crate: CargoCrate;
+  /** Module path from the crate root, e.g. `["geo", "shapes"]` */
+  path: string[];
+  /** True if every module on the path is exported */
+  reachable: boolean;
+  /** True if the `mod` declaration of the module has outer docs */
+  documented: boolean;
+}
+
+// Types that can be the self type of an inherent impl block
+const selfTypeKinds = ["struct_item", "enum_item", "union_item", "type_item"];
+// Items owning the visibility of the nodes they contain
+const ownerKinds = [
+  "trait_item",
+  "struct_item",
+  "enum_item",
+  "union_item",
+  "impl_item",
+];
+
+/**
+ * Finds the inline modules enclosing a node, the item owning it (trait, type
+ * or impl block) if any, and the name under which the item is reachable.
+ */
+function getItemOwner(node: SgNode) {
+  const ancestors: SgNode[] = [];
+  for (let p = node.parent(); p; p = p.parent()) ancestors.push(p);
+  const mods = ancestors.filter((a) => a.kind() === "mod_item").reverse();
+  const owner = ancestors.find((a) => ownerKinds.includes(a.kind()));
+  const name =
+    owner?.kind() === "impl_item"
+      ? getImplTypeName(owner)
+      : (owner || node).field("name")?.text();
+  return { mods, owner, name };
+}
+
+export async function readText(filename: string): Promise<string | undefined> {
+  const [file] = await workspace.findFiles(filename, { readText: true });
+  return file?.content;
+}
+
+/**
+ * Splits a comma separated list at the top level, ignoring nested braces.
+ */
+function splitTopLevel(text: string): string[] {
+  const parts: string[] = [];
+  let depth = 0;
+  let start = 0;
+  for (let i = 0; i < text.length; i++) {
+    if (text[i] === "{") depth++;
+    else if (text[i] === "}") depth--;
+    else if (text[i] === "," && depth === 0) {
+      parts.push(text.slice(start, i));
+      start = i + 1;
+    }
+  }
+  parts.push(text.slice(start));
+  return parts.map((p) => p.trim()).filter((p) => p);
+}
+
+/**
+ * Expands a use tree such as `a::{b, c::{d as e, self}}` into the list of
+ * imported paths (`a::b`, `a::c::d`, `a::c`) with the name each one is
+ * imported as (`b`, `e`, `c`, or `*` for globs).
+ */
+function expandUseTree(
+  tree: string,
+  prefix = ""
+): { target: string; name: string }[] {
+  tree = tree.trim();
+  const brace = tree.indexOf("{");
+  if (brace < 0) {
+    const alias = /\s+as\s+(\w+)$/.exec(tree);
+    let target = tree.replace(/\s+as\s+\w+$/, "").trim();
+    target = target === "self" ? prefix.replace(/::$/, "") : prefix + target;
+    return [{ target, name: alias?.[1] || target.split("::").pop() }];
+  }
+  const head = tree.slice(0, brace).trim();
+  const inner = tree.slice(brace + 1, tree.lastIndexOf("}"));
+  return splitTopLevel(inner).flatMap((t) => expandUseTree(t, prefix + head));
+}
+
+/**
+ * Resolves a `use` path written in module `modPath` to a path from the crate
+ * root, e.g. `super::geo::Line` to `crate::geo::Line`.
+ */
+function resolveUsePath(target: string, modPath: string[]): string {
+  const segments = target
+    .replace(/^::/, "")
+    .split("::")
+    .map((s) => s.trim());
+  if (segments[0] === "crate") return segments.join("::");
+  const base = [...modPath];
+  if (segments[0] === "self") segments.shift();
+  while (segments[0] === "super") {
+    segments.shift();
+    base.pop();
+  }
+  // 2018 edition relative path, or an extern crate that will never match
+  return ["crate", ...base, ...segments].join("::");
+}
+
+// Items that can be the target of an intra-doc link
+const symbolKinds = [
+  "function_item",
+  "function_signature_item",
+  "struct_item",
+  "enum_item",
+  "union_item",
+  "type_item",
+  "trait_item",
+  "const_item",
+  "static_item",
+  "macro_definition",
+  "mod_item",
+  "associated_type",
+  "enum_variant",
+  "field_declaration",
+];
+// Items whose body declares members addressed as `Item::member`
+const containerKinds = [...ownerKinds, "mod_item"];
+
+/**
+ * Collects the paths of the items declared in a container, relative to the
+ * module `prefix`, including the members of types, traits and impl blocks.
+ */
+export function collectSymbols(
+  container: SgNode,
+  prefix: string[],
+  symbols: Set<string>
+) {
+  for (const node of container.children()) {
+    const kind = node.kind();
+    // foreign items live in the namespace of the enclosing module
+    if (kind === "foreign_mod_item") {
+      const body = node.field("body");
+      if (body) collectSymbols(body, prefix, symbols);
+      continue;
+    }
+    const name =
+      kind === "impl_item"
+        ? getImplTypeName(node)?.split("::").pop()
+        : node.field("name")?.text();
+    if (!name) continue;
+    if (symbolKinds.includes(kind)) symbols.add([...prefix, name].join("::"));
+    if (kind === "macro_definition" && isMacroExported(node)) symbols.add(name);
+    const body = containerKinds.includes(kind) && node.field("body");
+    if (body) collectSymbols(body, [...prefix, name], symbols);
+  }
+}
+
+/**
+ * Returns the names brought into scope by a use tree, e.g. `b`, `e` and `c`
+ * for `a::{b, c::{d as e, self}}`, and `*` for glob imports.
+ */
+export function getImportedNames(tree: string): string[] {
+  return expandUseTree(tree)
+    .map((e) => e.name)
+    .filter(Boolean);
+}
+
+/**
+ * Returns the names under which the dependencies of a manifest are used in
+ * paths, including dev and target specific dependencies.
+ */
+function getDependencyNames(toml: any): Set<string> {
+  const tables = [toml, ...Object.values(toml.target || {})].flatMap(
+    (t: any) => [t?.dependencies, t?.["dev-dependencies"]]
+  );
+  return new Set(
+    tables.flatMap((t) => Object.keys(t || {})).map((n) => n.replace(/-/g, "_"))
+  );
+}
+
+/**
+ * Rust project model built from the `Cargo.toml` manifests and the `mod`
+ * declarations of the crates, used to find the publicly reachable items.
+ */
+export class CargoProject {
+  readonly crates: CargoCrate[] = [];
+  /** Modules indexed by source file name */
+  readonly modules = new Map<string, RustModule>();
+  /** Paths (`crate::a::B`, `crate::a::*`) re-exported by `pub use` in reachable modules */
+  readonly reexports = new Set<string>();
+  /** Public paths of re-exported paths, e.g. `crate::B` for `crate::a::B` */
+  private readonly reexportPaths = new Map<string, string[]>();
+  private readonly manifests = new Map<string, string | undefined>();
+  private readonly loaded = new Set<string>();
+
+  constructor(
+    private readonly sg: AstGrep,
+    private readonly exported: RegExp
+  ) {}
+
+  /**
+   * Loads the crates containing the given Rust source files.
+   */
+  async load(filenames: string[]) {
+    for (const filename of filenames) {
+      const manifest = await this.findManifest(path.dirname(filename));
+      if (manifest) await this.loadManifest(manifest);
+    }
+    dbg(
+      `crates: %o`,
+      this.crates.map(({ name, root }) => ({ name, root }))
+    );
+    dbg(`modules: %d, re-exports: %o`, this.modules.size, [...this.reexports]);
+  }
+
+  /**
+   * Returns the module backed by the given file, if it belongs to a crate.
+   */
+  getModule(filename: string): RustModule | undefined {
+    return this.modules.get(filename);
+  }
+
+  /**
+   * Checks whether a declaration is part of the public API of its crate:
+   * exported itself and reachable from the crate root through exported
+   * modules or `pub use` re-exports. Returns undefined if the file does not
+   * belong to a known crate.
+   */
+  isPublicApi(node: SgNode, filename: string): boolean | undefined {
+    const module = this.modules.get(filename);
+    if (!module) return undefined;
+    if (node.kind() === "source_file") return module.reachable;
+    if (node.kind() === "macro_definition") return isMacroExported(node);
+
+    const { mods, owner, name } = getItemOwner(node);
+    // Trait items and enum variants inherit the visibility of their parent,
+    // fields need both their own and their parent's, impl items their own
+    // and their self type's
+    const visible = !owner
+      ? this.isExported(node)
+      : owner.kind() === "impl_item"
+        ? this.isExported(node) && this.isSelfTypePublic(owner, filename)
+        : this.isExported(owner) &&
+          (owner.kind() === "struct_item" || owner.kind() === "union_item"
+            ? this.isExported(node)
+            : true);
+    if (!visible || !name) return false;
+
+    const segments = [
+      "crate",
+      ...module.path,
+      ...mods.map((m) => m.field("name")?.text()),
+      name,
+    ];
+    const reachable =
+      module.reachable && mods.every((m) => this.isExported(m));
+    if (reachable) return true;
+
+    // re-exported item, module or glob
+    if (this.reexports.has(segments.join("::"))) return true;
+    for (let i = segments.length - 1; i > 1; i--) {
+      const prefix = segments.slice(0, i).join("::");
+      if (this.reexports.has(prefix) || this.reexports.has(`${prefix}::*`))
+        return true;
+    }
+    return false;
+  }
+
+  /**
+   * Returns the shortest path of a public item as seen from outside of its
+   * crate, e.g. `my_crate::Point` for `crate::geo::Point` re-exported at the
+   * root. Members resolve to their type or trait. Returns undefined if the
+   * item is not reachable from outside of the crate.
+   */
+  getItemPath(node: SgNode, filename: string): string | undefined {
+    const module = this.modules.get(filename);
+    if (!module) return undefined;
+    const { mods, name } = getItemOwner(node);
+    if (!name) return undefined;
+    if (node.kind() === "macro_definition")
+      return isMacroExported(node)
+        ? `${module.crate.name}::${name}`
+        : undefined;
+    if (!this.isPublicApi(node, filename)) return undefined;
+
+    const segments = [
+      "crate",
+      ...module.path,
+      ...mods.map((m) => m.field("name")?.text()),
+      name,
+    ];
+    const paths: string[][] = [];
+    if (module.reachable && mods.every((m) => this.isExported(m)))
+      paths.push(segments);
+    // re-exports of the item, of one of its modules, or globs of a module
+    for (let i = segments.length; i > 1; i--) {
+      const prefix = segments.slice(0, i).join("::");
+      const rest = segments.slice(i);
+      const reexported = [
+        ...(this.reexportPaths.get(prefix) || []),
+        ...(rest.length ? this.reexportPaths.get(`${prefix}::*`) || [] : []),
+      ];
+      for (const p of reexported) paths.push([...p.split("::"), ...rest]);
+    }
+    if (!paths.length) return undefined;
+    const [shortest] = paths.sort((a, b) => a.length - b.length);
+    return [module.crate.name, ...shortest.slice(1)].join("::");
+  }
+
+  /**
+   * Returns the root directory of the Cargo workspace containing a crate, or
+   * the crate directory if it is not a workspace member.
+   */
+  async getWorkspaceDir(crate: CargoCrate): Promise<string> {
+    for (let dir = crate.dir; ; ) {
+      const toml = parsers.TOML(
+        (await readText(path.join(dir, "Cargo.toml"))) || ""
+      );
+      if (toml?.workspace) return dir;
+      const parent = path.dirname(dir);
+      if (parent === dir) return crate.dir;
+      dir = parent;
+    }
+  }
+
+  /**
+   * Checks whether the self type of an impl block is part of the public API,
+   * when it is declared next to the impl block. Types declared elsewhere are
+   * assumed to be public.
+   */
+  private isSelfTypePublic(impl: SgNode, filename: string): boolean {
+    const name = getImplTypeName(impl);
+    const decl =
+      name &&
+      !name.includes("::") &&
+      impl
+        .parent()
+        ?.children()
+        .find(
+          (c) =>
+            selfTypeKinds.includes(c.kind()) && c.field("name")?.text() === name
+        );
+    if (!decl) return true;
+    return this.isPublicApi(decl, filename) !== false;
+  }
+
+  private isExported(node: SgNode): boolean {
+    if (node.kind() === "visibility_modifier")
+      return this.exported.test(node.text());
+    const visibility = node
+      .children()
+      .find((c) => c.kind() === "visibility_modifier");
+    return !!visibility && this.exported.test(visibility.text());
+  }
+
+  private async findManifest(dir: string): Promise<string | undefined> {
+    if (this.manifests.has(dir)) return this.manifests.get(dir);
+    const candidate = path.join(dir, "Cargo.toml");
+    const parent = path.dirname(dir);
+    const manifest =
+      (await readText(candidate)) !== undefined
+        ? candidate
+        : parent !== dir
+          ? await this.findManifest(parent)
+          : undefined;
+    this.manifests.set(dir, manifest);
+    return manifest;
+  }
+
+  private async loadManifest(manifest: string) {
+    if (this.loaded.has(manifest)) return;
+    this.loaded.add(manifest);
+
+    const dir = path.dirname(manifest);
+    const toml = parsers.TOML(await readText(manifest));
+    if (!toml) return;
+
+    // workspace members are crates of their own
+    for (const member of toml.workspace?.members || []) {
+      const members = await workspace.findFiles(
+        path.join(dir, member, "Cargo.toml")
+      );
+      for (const { filename } of members) await this.loadManifest(filename);
+    }
+    if (!toml.package?.name) return;
+
+    const libPath = path.join(dir, toml.lib?.path || "src/lib.rs");
+    const lib = (await readText(libPath)) !== undefined;
+    const root = lib
+      ? libPath
+      : path.join(dir, toml.bin?.[0]?.path || "src/main.rs");
+    if (!lib && (await readText(root)) === undefined) return;
+
+    const crate: CargoCrate = {
+      name: (toml.lib?.name || toml.package.name).replace(/-/g, "_"),
+      dir,
+      root,
+      lib,
+      symbols: new Set(),
+      docsrs: false,
+      dependencies: getDependencyNames(toml),
+    };
+    this.crates.push(crate);
+    await this.loadModule(crate, root, [], true);
+  }
+
+  private async loadModule(
+    crate: CargoCrate,
+    filename: string,
+    modPath: string[],
+    reachable: boolean,
+    documented = false
+  ) {
+    if (this.modules.has(filename)) return;
+    this.modules.set(filename, {
+      crate,
+      path: modPath,
+      reachable,
+      documented,
+    });
+
+    const { matches } = await this.sg.search(
+      "rust",
+      filename,
+      { rule: { kind: "source_file" } },
+      {}
+    );
+    if (!matches.length) return;
+    collectSymbols(matches[0], modPath, crate.symbols);
+    if (/\bcfg_attr\s*\(\s*docsrs\b/.test(matches[0].text()))
+      crate.docsrs = true;
+    // crate roots and mod.rs files own their directory, foo.rs owns foo/
+    const dir =
+      filename === crate.root || path.basename(filename) === "mod.rs"
+        ? path.dirname(filename)
+        : filename.replace(/\.rs$/, "");
+    await this.loadDeclarations(
+      crate,
+      filename,
+      matches[0],
+      modPath,
+      reachable,
+      dir
+    );
+  }
+
+  private async loadDeclarations(
+    crate: CargoCrate,
+    filename: string,
+    container: SgNode,
+    modPath: string[],
+    reachable: boolean,
+    dir: string
+  ) {
+    for (const node of container.children()) {
+      const kind = node.kind();
+      if (kind === "mod_item") {
+        const name = node
+          .field("name")
+          ?.text()
+          .replace(/^r#/, "");
+        if (!name) continue;
+        const childReachable = reachable && this.isExported(node);
+        const body = node.field("body");
+        if (body) {
+          await this.loadDeclarations(
+            crate,
+            filename,
+            body,
+            [...modPath, name],
+            childReachable,
+            path.join(dir, name)
+          );
+        } else {
+          const file = await this.resolveModuleFile(node, name, filename, dir);
+          if (file)
+            await this.loadModule(
+              crate,
+              file,
+              [...modPath, name],
+              childReachable,
+              hasOuterDocs(node)
+            );
+        }
+      } else if (
+        kind === "use_declaration" &&
+        reachable &&
+        this.isExported(node)
+      ) {
+        const tree = node.field("argument")?.text();
+        for (const { target, name } of tree ? expandUseTree(tree) : []) {
+          const resolved = resolveUsePath(target, modPath);
+          this.reexports.add(resolved);
+          // `as _` only brings trait methods in scope
+          if (name === "_") continue;
+          // globs re-export the items of a module in this module
+          const publicPath = ["crate", ...modPath];
+          if (name !== "*") publicPath.push(name);
+          this.reexportPaths.set(resolved, [
+            ...(this.reexportPaths.get(resolved) || []),
+            publicPath.join("::"),
+          ]);
+        }
+      }
+    }
+  }
+
+  private async resolveModuleFile(
+    node: SgNode,
+    name: string,
+    filename: string,
+    dir: string
+  ): Promise<string | undefined> {
+    // #[path = "..."] is relative to the directory of the current file
+    for (let p = node.prev(); p?.kind() === "attribute_item"; p = p.prev()) {
+      const m = /^#\[\s*path\s*=\s*"([^"]+)"\s*\]$/.exec(p.text());
+      if (m) return path.join(path.dirname(filename), m[1]);
+    }
+    for (const candidate of [
+      path.join(dir, `${name}.rs`),
+      path.join(dir, name, "mod.rs"),
+    ])
+      if ((await readText(candidate)) !== undefined) return candidate;
+    dbg(`module %s not found in %s`, name, dir);
+    return undefined;
+  }
+}
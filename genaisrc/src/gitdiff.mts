@@ -0,0 +1,84 @@
+import { type SgNode } from "@genaiscript/plugin-ast-grep";
+
+const dbg = host.logger("script:gitdiff");
+
+/**
+ * A range of changed lines, 0-based and inclusive like ast-grep positions.
+ */
+export interface LineRange {
+  start: number;
+  end: number;
+}
+
+/**
+ * Returns true if a git ref names a commit.
+ */
+export async function isCommitRef(ref: string): Promise<boolean> {
+  const res = await host.exec("git", [
+    "rev-parse",
+    "--verify",
+    "--quiet",
+    `${ref}^{commit}`,
+  ]);
+  return !res.exitCode;
+}
+
+/**
+ * Returns the lines of a file changed since a git ref, from the hunks of
+ * `git diff` against the working tree, or undefined if the whole file is new.
+ */
+export async function getChangedLines(
+  ref: string,
+  filename: string
+): Promise<LineRange[] | undefined> {
+  const untracked = await host.exec("git", [
+    "ls-files",
+    "--others",
+    "--exclude-standard",
+    "--",
+    filename,
+  ]);
+  if (untracked.stdout?.trim()) {
+    dbg(`%s is untracked`, filename);
+    return undefined;
+  }
+
+  const res = await host.exec("git", [
+    "diff",
+    "--unified=0",
+    "--no-color",
+    "--no-ext-diff",
+    ref,
+    "--",
+    filename,
+  ]);
+  if (res.exitCode) {
+    env.output.warn(`git diff failed for ${filename}, processing all of it`);
+    dbg(`%s`, res.stderr);
+    return undefined;
+  }
+
+  const ranges: LineRange[] = [];
+  for (const [, start, count] of res.stdout.matchAll(
+    /^@@ -\S+ \+(\d+)(?:,(\d+))? @@/gm
+  )) {
+    const line = parseInt(start) - 1;
+    const lines = count === undefined ? 1 : parseInt(count);
+    // deleted lines sit between two lines of the new file, mark both
+    ranges.push(
+      lines
+        ? { start: line, end: line + lines - 1 }
+        : { start: line, end: line + 1 }
+    );
+  }
+  dbg(`%s changed lines: %o`, filename, ranges);
+  return ranges;
+}
+
+/**
+ * Returns true if a node spans some of the changed lines.
+ */
+export function isChanged(node: SgNode, ranges: LineRange[]): boolean {
+  const { start, end } = node.range();
+  return ranges.some((r) => r.start <= end.line && r.end >= start.line);
+}
@@ -1,4 +1,12 @@
-import { type SgNode, type SgRule } from "@genaiscript/plugin-ast-grep";
+import {
+  type astGrep,
+  type SgNode,
+  type SgRule,
+} from "@genaiscript/plugin-ast-grep";
+
+/** The ast-grep instance shared by the script */
+export type AstGrep = Awaited<ReturnType<typeof astGrep>>;
+
 /**
  * Represents the kinds of entities that can be documented or processed within the language operations.
  * Can include modules, types, functions, properties, or variables.
@@ -10,6 +18,14 @@ export type EntityKind =
   | "property"
   | "variable";
 
+/**
+ * Matches the comments opting code out of the docs:
+ * `continuous-comments: ignore` on an entity, `ignore-next` above it and
+ * `ignore-file` anywhere in the file.
+ */
+export const ignoreMarkerRegex =
+  /\bcontinuous-comments:\s*(ignore-file|ignore-next|ignore)\b/;
+
 /**
  * Defines methods for manipulating language-specific documentation and comment nodes.
  *
@@ -32,6 +48,7 @@ export type EntityKind =
  *
  * @property getCommentText - Formats documentation text for insertion as a comment.
  * @param docs - Raw documentation string.
+ * @param decl - Optional declaration node being documented, used to match existing comment styles.
  * @returns Formatted comment text.
  *
  * @property getLanguageSystemPromptName - Gets the system prompt identifier for the language.
@@ -41,13 +58,47 @@ export type EntityKind =
  * @param _ - Chat context (unused).
  * @param declKind - Declaration kind.
  * @param declRef - Declaration reference.
+ * @param declNode - Optional declaration node, for prompts that inspect the AST.
+ * @param filename - Optional name of the file containing the declaration.
  * @returns Prompt template string.
  *
+ * @property loadProject - Optionally loads project metadata (e.g. Cargo.toml) covering the files to process.
+ * @param sg - ast-grep instance.
+ * @param filenames - Files to process.
+ *
+ * @property prioritizeMatches - Optionally filters and reorders matched nodes using the project metadata.
+ * @param matches - Matched nodes.
+ * @param filename - File containing the matches.
+ * @param exportsOnly - If true, drop nodes that are not part of the public API.
+ * @returns Matched nodes, most visible first.
+ *
+ * @property addJudgePrompt - Optionally adds language-specific criteria to the judge prompt.
+ * @param _ - Chat context.
+ * @param declNode - Declaration node being documented.
+ * @param docsRef - Reference to the docs being judged.
+ *
+ * @property verifyDocs - Optionally checks the generated docs of a file, e.g. by running their doctests.
+ * @param filename - File containing the docs.
+ * @param content - Candidate content of the file, with the docs applied.
+ * @param docs - Generated docs, as inserted in the content.
+ * @returns For each doc, true if it should be kept.
+ *
+ * @property isDocStale - Optionally compares existing docs with their declaration, without calling the LLM.
+ * @param declNode - Documented declaration node.
+ * @param docNodes - Doc comment nodes of the declaration.
+ * @returns False if the docs still match the declaration and can be left as is.
+ *
+ * @property isIgnored - Optionally checks whether a matched node is opted out with an ignore marker comment.
+ * @param node - Matched node.
+ * @returns True if the node must not be documented nor updated.
+ *
  * @property addGenerateDocPrompt - Returns a documentation generation prompt template string.
  * @param _ - Chat context (unused).
  * @param declKind - Kind of the declaration node.
  * @param declRef - Declaration reference.
  * @param fileRef - File reference.
+ * @param declNode - Optional declaration node, for prompts that inspect the AST.
+ * @param filename - Optional name of the file containing the declaration.
  * @returns Prompt template string.
  */
 export interface LanguageOps {
@@ -64,20 +115,48 @@ export interface LanguageOps {
   getCommentInsertionNode: (node: SgNode) => SgNode;
 
   /** Given a string of documentation, return the text to insert as a comment */
-  getCommentText: (docs: string) => string;
+  getCommentText: (docs: string, decl?: SgNode) => string;
 
   getLanguageSystemPromptName: () => string;
 
   addUpdateDocPrompt: (
     _: ChatGenerationContext,
     declKind: any,
-    declRef: string
+    declRef: string,
+    declNode?: SgNode,
+    filename?: string
   ) => PromptTemplateString;
 
+  loadProject?: (sg: AstGrep, filenames: string[]) => Promise<void>;
+
+  prioritizeMatches?: (
+    matches: SgNode[],
+    filename: string,
+    exportsOnly: boolean
+  ) => SgNode[];
+
+  addJudgePrompt?: (
+    _: ChatGenerationContext,
+    declNode: SgNode,
+    docsRef: string
+  ) => void;
+
+  verifyDocs?: (
+    filename: string,
+    content: string,
+    docs: string[]
+  ) => Promise<boolean[]>;
+
+  isDocStale?: (declNode: SgNode, docNodes: SgNode[]) => boolean;
+
+  isIgnored?: (node: SgNode) => boolean;
+
   addGenerateDocPrompt: (
     _: ChatGenerationContext,
     declKind: ReturnType<SgNode["kind"]>,
     declRef: string,
-    fileRef: string
+    fileRef: string,
+    declNode?: SgNode,
+    filename?: string
   ) => PromptTemplateString;
 }
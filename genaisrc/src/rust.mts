@@ -2,41 +2,1495 @@ import { type SgNode, type SgRule } from "@genaiscript/plugin-ast-grep";
 
 const dbg = host.logger("script:rust");
 
-import type { EntityKind, LanguageOps } from "./langops.mts";
+import {
+  CargoProject,
+  collectSymbols,
+  getImportedNames,
+  readText,
+} from "./cargo.mts";
+import {
+  ignoreMarkerRegex,
+  type AstGrep,
+  type EntityKind,
+  type LanguageOps,
+} from "./langops.mts";
+import {
+  attributeKinds,
+  getDocStyle,
+  getImplTypeName,
+  getPrecedingSiblings,
+  hasOuterDocs,
+  innerDocPatterns,
+  isMacroExported,
+  itemPrefixKinds,
+  macroExportRegex,
+  outerDocPatterns,
+  type DocStyle,
+} from "./rustsyntax.mts";
+
+const itemPrefixRule: SgRule = {
+  any: itemPrefixKinds.map((kind) => ({ kind })),
+};
+
+// Rules matching the doc comments recognized by getDocStyle
+const outerDocRule: SgRule = { any: Object.values(outerDocPatterns) };
+const innerDocRule: SgRule = { any: Object.values(innerDocPatterns) };
+// Docs pulled from another file are owned by that file, never rewrite them
+const includedDocRegex = /^#!?\[\s*doc\s*=\s*include_str\s*!/;
+const includedDocRule: SgRule = {
+  any: ["attribute_item", "inner_attribute_item"].map((kind) => ({
+    kind,
+    regex: includedDocRegex.source,
+  })),
+};
+// Test-only items: #[test], #[tokio::test], #[bench] and #[cfg(test)] items
+const testAttributes = ["test", "bench", "cfg(test)"];
+// Conditional compilation: #[cfg(pred)] gates an item, #[cfg_attr(pred, attrs)]
+// applies attributes conditionally
+const cfgRegex = /^#!?\[\s*cfg\s*\(([\s\S]*)\)\s*\]$/;
+const cfgAttrRegex = /^#!?\[\s*cfg_attr\s*\(([\s\S]*)\)\s*\]$/;
+const featureRegex = /\bfeature\s*=\s*"([^"]+)"/g;
+// #[cfg_attr(docsrs, doc(cfg(...)))] shows feature requirements on docs.rs
+const docCfgRegex = /\bdoc\s*\(\s*cfg\s*\(/;
+// Functions defining procedural macros
+const procMacroRegex = /^#\[\s*proc_macro(_derive|_attribute)?\b/;
+// `impl Trait for Type` blocks have a `trait` field, inherent impls do not
+const traitImplRule: SgRule = { field: "trait", regex: "." };
+// Items that get a `# Examples` section with a doctest
+const exampleKinds = [
+  "function_item",
+  "function_signature_item",
+  "struct_item",
+  "enum_item",
+  "trait_item",
+  "union_item",
+];
+// APIs that touch the file system, network or processes, doctests using them
+// should be compiled but not run
+const ioRegex =
+  /\b(std::(fs|net|process)|fs::|File::|OpenOptions|TcpStream|TcpListener|UdpSocket|Command::|stdin|reqwest|hyper::)/;
+// Expressions that may panic, as flagged by clippy's missing_panics_doc
+const panicRule: SgRule = {
+  any: [
+    {
+      kind: "call_expression",
+      has: {
+        field: "function",
+        kind: "field_expression",
+        has: { field: "field", regex: "^(unwrap|expect)$" },
+      },
+    },
+    {
+      kind: "macro_invocation",
+      has: {
+        field: "macro",
+        regex:
+          "^(std::)?(panic|assert|assert_eq|assert_ne|unreachable|todo|unimplemented)$",
+      },
+    },
+    { kind: "index_expression" },
+  ],
+};
+// Nodes allowed before the inner docs at the top of a file
+const fileHeaderKinds = [
+  "shebang",
+  "line_comment",
+  "block_comment",
+  "inner_attribute_item",
+];
+
+/**
+ * Returns the last line of a node, ignoring the line break that ends line
+ * comments.
+ */
+function getEndLine(node: SgNode): number {
+  return node.range().start.line + node.text().trimEnd().split("\n").length - 1;
+}
+
+/**
+ * Returns the leading shebang, comments and inner attributes of a file, or of
+ * the body of an inline module.
+ */
+function getFileHeader(root: SgNode): SgNode[] {
+  const children = root.children().filter((n) => n.isNamed());
+  const end = children.findIndex((n) => !fileHeaderKinds.includes(n.kind()));
+  return end < 0 ? children : children.slice(0, end);
+}
+
+/**
+ * Returns the inner docs at the top of the body of an inline module that has
+ * no outer docs, which then document the module.
+ */
+function getInlineModuleDocs(node: SgNode | undefined): SgNode[] {
+  const body = node?.kind() === "mod_item" ? node.field("body") : undefined;
+  if (!body || hasOuterDocs(node)) return [];
+  return getFileHeader(body).filter((n) => getDocStyle(n, true));
+}
+
+/**
+ * Returns the inline modules enclosing a node, outermost first.
+ */
+function getModules(node: SgNode): SgNode[] {
+  const mods: SgNode[] = [];
+  for (let p = node.parent(); p; p = p.parent())
+    if (p.kind() === "mod_item") mods.unshift(p);
+  return mods;
+}
+
+/**
+ * Converts an attribute pattern such as `test` or `doc(hidden)` to a regex
+ * matching the attribute, ignoring whitespace and path prefixes, so that
+ * `test` also matches `#[tokio::test]` and `#[test(...)]`.
+ */
+function getAttributeRegex(pattern: string): string {
+  const tokens = (pattern.match(/\w+|::|\S/g) || []).map((t) =>
+    t.replace(/[.*+?^${}()|[\]\\]/g, "\\$&")
+  );
+  const end = pattern.includes("(") ? "\\]" : "[\\](]";
+  return `^#\\[\\s*(\\w+::)*${tokens.join("\\s*")}\\s*${end}`;
+}
+
+/**
+ * Matches items annotated with an outer attribute whose text matches `regex`.
+ */
+function followsAttribute(regex: string): SgRule {
+  return {
+    follows: {
+      kind: "attribute_item",
+      regex,
+      stopBy: { not: itemPrefixRule },
+    },
+  };
+}
+
+/**
+ * Checks whether a node is a struct field, a tuple struct field or an enum
+ * variant, as matched by the `property` kind.
+ */
+function isProperty(node: SgNode | undefined): boolean {
+  const kind = node?.kind();
+  return (
+    kind === "field_declaration" ||
+    kind === "enum_variant" ||
+    node?.parent()?.kind() === "ordered_field_declaration_list"
+  );
+}
+
+/**
+ * Describes a tuple field anchored on its visibility or type with its position
+ * and full text, e.g. `field 0: pub u32`, or returns undefined for other
+ * nodes.
+ */
+function getTupleFieldText(node: SgNode | undefined): string | undefined {
+  const list = node?.parent();
+  if (list?.kind() !== "ordered_field_declaration_list") return undefined;
+  const fields: { visibility?: SgNode; type: SgNode }[] = [];
+  let visibility: SgNode | undefined;
+  for (const child of list.children()) {
+    if (!child.isNamed() || itemPrefixKinds.includes(child.kind())) continue;
+    if (child.kind() === "visibility_modifier") visibility = child;
+    else {
+      fields.push({ visibility, type: child });
+      visibility = undefined;
+    }
+  }
+  const start = node.range().start.index;
+  const index = fields.findIndex(
+    (f) =>
+      f.type.range().start.index === start ||
+      f.visibility?.range().start.index === start
+  );
+  if (index < 0) return undefined;
+  const { visibility: vis, type } = fields[index];
+  return `field ${index}: ${vis ? `${vis.text()} ` : ""}${type.text()}`;
+}
+
+/**
+ * Returns the struct, enum or variant declaring a property.
+ */
+function getPropertyParent(node: SgNode): SgNode {
+  let current = node.parent();
+  while (
+    current &&
+    !["struct_item", "enum_item", "enum_variant", "union_item"].includes(
+      current.kind()
+    )
+  )
+    current = current.parent();
+  return current || node.parent();
+}
+
+/**
+ * Describes the role of an item declared in a trait, or returns undefined for
+ * items outside traits.
+ */
+function getTraitItemRole(node: SgNode | undefined): string | undefined {
+  if (node?.parent()?.parent()?.kind() !== "trait_item") return undefined;
+  switch (node.kind()) {
+    case "function_signature_item":
+      return "required method";
+    case "function_item":
+      return "provided method";
+    case "associated_type":
+      return "associated type";
+    case "const_item":
+      return "associated constant";
+    default:
+      return undefined;
+  }
+}
+
+/**
+ * Describes the kind of macro defined by a node: a `macro_rules!` macro or
+ * a function annotated with a proc macro attribute. Returns undefined for
+ * other items.
+ */
+function getMacroKind(node: SgNode | undefined): string | undefined {
+  if (node?.kind() === "macro_definition") return "declarative macro";
+  if (node?.kind() !== "function_item") return undefined;
+  for (const attribute of getPrecedingSiblings(node, itemPrefixKinds)) {
+    const m = procMacroRegex.exec(attribute.text());
+    if (!m) continue;
+    if (m[1] === "_derive") return "derive macro";
+    if (m[1] === "_attribute") return "attribute macro";
+    return "function-like procedural macro";
+  }
+  return undefined;
+}
+
+/**
+ * Adds guidance to document the contract of a trait item, which is what
+ * implementors and callers rely on.
+ */
+function addTraitItemPrompt(
+  _: ChatGenerationContext,
+  node: SgNode | undefined
+) {
+  const role = getTraitItemRole(node);
+  if (!role) return;
+  const trait = node.parent().parent().field("name")?.text();
+  _.$`The declaration is a ${role} of the trait ${trait}.
+- Describe the contract implementors must uphold: preconditions, postconditions, invariants and side effects.
+- Describe what callers can rely on, regardless of the implementation.`.role(
+    "system"
+  );
+  if (role === "provided method")
+    _.$`- Describe the default behavior and when implementors should override it.`.role(
+      "system"
+    );
+}
+
+/**
+ * Checks whether a node is a function or static declared in an extern block.
+ */
+function isForeignItem(node: SgNode | undefined): boolean {
+  return node?.parent()?.parent()?.kind() === "foreign_mod_item";
+}
+
+/**
+ * Describes the role of an item at an FFI boundary, or returns undefined for
+ * items that are not involved in FFI.
+ */
+function getFfiRole(node: SgNode | undefined): string | undefined {
+  switch (node?.kind()) {
+    case "foreign_mod_item":
+      return "extern block";
+    case "union_item":
+      return "union";
+    case "function_signature_item":
+      return isForeignItem(node) ? "foreign function" : undefined;
+    case "static_item":
+      if (isForeignItem(node)) return "foreign static";
+      return node.children().some((c) => c.kind() === "mutable_specifier")
+        ? "mutable static"
+        : undefined;
+    case "function_item":
+      return node
+        .children()
+        .some(
+          (c) =>
+            c.kind() === "function_modifiers" && /\bextern\b/.test(c.text())
+        ) ||
+        getPrecedingSiblings(node, attributeKinds).some((a) =>
+          /^#\[\s*(unsafe\s*\(\s*)?no_mangle\b/.test(a.text())
+        )
+        ? "function exported to foreign code"
+        : undefined;
+    default:
+      return undefined;
+  }
+}
+
+/**
+ * Adds guidance to document the expectations of foreign code and unsafe
+ * callers: ownership, nullability and thread-safety.
+ */
+function addFfiPrompt(_: ChatGenerationContext, node: SgNode | undefined) {
+  const role = getFfiRole(node);
+  if (!role) return;
+  _.$`The declaration is a ${role}, used across an FFI boundary or from unsafe code.
+- Document the ownership of pointers and resources passed in and returned: who allocates and who frees them.
+- Document which pointers may be null, and how long they must stay valid.
+- Document the thread-safety expectations: whether it may be called or accessed concurrently, and from which threads.`.role(
+    "system"
+  );
+  if (role === "union")
+    _.$`- Describe which field is valid in which situation, e.g. the tag that selects it.`.role(
+      "system"
+    );
+}
+
+/**
+ * Finds the rustdoc sections a declaration needs to pass clippy's
+ * `missing_errors_doc`, `missing_panics_doc` and `missing_safety_doc`,
+ * with the reason each one is required.
+ */
+function getRequiredSections(
+  node: SgNode | undefined
+): { heading: string; reason: string }[] {
+  const kind = node?.kind();
+  const isUnsafe = () =>
+    node
+      .children()
+      .some(
+        (c) =>
+          c.text() === "unsafe" ||
+          (c.kind() === "function_modifiers" && /\bunsafe\b/.test(c.text()))
+      );
+  if (kind === "trait_item")
+    return isUnsafe()
+      ? [
+          {
+            heading: "Safety",
+            reason:
+              "it is an unsafe trait, list the invariants implementors must uphold",
+          },
+        ]
+      : [];
+  if (kind === "union_item")
+    return [
+      {
+        heading: "Safety",
+        reason:
+          "reading a union field is unsafe, describe how callers know which field is valid",
+      },
+    ];
+  if (kind === "static_item")
+    return isForeignItem(node) ||
+      node.children().some((c) => c.kind() === "mutable_specifier")
+      ? [
+          {
+            heading: "Safety",
+            reason:
+              "accessing the static is unsafe, list the synchronization callers must ensure",
+          },
+        ]
+      : [];
+  if (kind !== "function_item" && kind !== "function_signature_item")
+    return [];
+
+  const sections: { heading: string; reason: string }[] = [];
+  if (/\bResult\b/.test(node.field("return_type")?.text() || ""))
+    sections.push({
+      heading: "Errors",
+      reason: "it returns a Result, list the conditions under which it fails",
+    });
+  const panic = node.field("body")?.find({ rule: panicRule });
+  if (panic)
+    sections.push({
+      heading: "Panics",
+      reason: `it may panic (\`${panic.text().split("\n")[0]}\`), list the conditions under which it panics`,
+    });
+  if (isUnsafe())
+    sections.push({
+      heading: "Safety",
+      reason:
+        "it is an unsafe function, list the invariants callers must uphold",
+    });
+  else if (isForeignItem(node) && !/^(pub\S*\s+)?safe\b/.test(node.text()))
+    sections.push({
+      heading: "Safety",
+      reason:
+        "it is a foreign function, list the invariants callers must uphold",
+    });
+  return sections;
+}
+
+/**
+ * Asks for the sections required by the rustdoc conventions.
+ */
+function addRequiredSectionsPrompt(
+  _: ChatGenerationContext,
+  node: SgNode | undefined
+) {
+  for (const { heading, reason } of getRequiredSections(node))
+    _.$`- Add a \`# ${heading}\` section since ${reason}.`.role("system");
+}
+
+// Backticked words that are not names of the documented function
+const docKeywords = new Set([
+  "true",
+  "false",
+  "self",
+  "mut",
+  "ref",
+  "fn",
+  "async",
+  "await",
+  "unsafe",
+  "move",
+  "dyn",
+  "impl",
+  "crate",
+  "super",
+  "bool",
+  "char",
+  "str",
+  "u8",
+  "u16",
+  "u32",
+  "u64",
+  "u128",
+  "usize",
+  "i8",
+  "i16",
+  "i32",
+  "i64",
+  "i128",
+  "isize",
+  "f32",
+  "f64",
+]);
+
+/**
+ * Returns the markdown of doc comment nodes, or undefined if an attribute
+ * is not a plain string literal.
+ */
+function getDocNodesText(docNodes: SgNode[]): string | undefined {
+  const lines: string[] = [];
+  for (const node of docNodes) {
+    if (getDocStyle(node) !== "attribute") {
+      lines.push(...getDocLines(node.text()));
+      continue;
+    }
+    const literal = /^#\[\s*doc\s*=\s*"((?:[^"\\]|\\.)*)"\s*\]$/.exec(
+      node.text()
+    );
+    if (!literal) return undefined;
+    lines.push(literal[1].replace(/\\(.)/g, "$1").trim());
+  }
+  return lines.join("\n");
+}
+
+/**
+ * Returns the body of a `# Heading` section of the docs, up to the next
+ * heading.
+ */
+function getDocSection(docs: string, heading: string): string | undefined {
+  const match = new RegExp(`^#+[ \\t]*${heading}[ \\t]*$`, "mi").exec(docs);
+  if (!match) return undefined;
+  const rest = docs.slice(match.index + match[0].length);
+  const next = /^#+\s/m.exec(rest);
+  return next ? rest.slice(0, next.index) : rest;
+}
+
+/**
+ * Compares the signature of a function with what its docs mention: the
+ * `# Arguments` entries, backticked names, the required sections and the
+ * error type of a returned `Result`. Returns why the docs look out of date.
+ */
+function getStaleDocsReasons(node: SgNode, docs: string): string[] {
+  // code blocks are examples, and their hidden lines look like headings
+  docs = docs.replace(/^\s*(```|~~~)[\s\S]*?^\s*\1\s*$/gm, "");
+  const reasons: string[] = [];
+
+  const params = node.field("parameters")?.children() || [];
+  const names = params
+    .filter((p) => p.kind() === "parameter")
+    .map((p) => p.field("pattern"))
+    .filter((p) => p?.kind() === "identifier")
+    .map((p) => p.text());
+  const patterns = params.some(
+    (p) =>
+      p.kind() === "parameter" && p.field("pattern")?.kind() !== "identifier"
+  );
+  const args = getDocSection(docs, "Arguments");
+  if (args !== undefined) {
+    const listed = [...args.matchAll(/^\s*[-*+]\s*`?(\w+)`?/gm)]
+      .map((m) => m[1])
+      .filter((n) => n !== "self");
+    const removed = listed.filter((n) => !names.includes(n));
+    if (removed.length && !patterns)
+      reasons.push(`unknown arguments ${removed.join(", ")}`);
+    const missing = names.filter(
+      (n) => !n.startsWith("_") && !listed.includes(n)
+    );
+    if (missing.length)
+      reasons.push(`undocumented arguments ${missing.join(", ")}`);
+  }
+
+  // names that no longer appear in the function, e.g. renamed parameters
+  const text = node.text();
+  const unknown = [
+    ...new Set([...docs.matchAll(/`([a-z_][a-z0-9_]*)`/g)].map((m) => m[1])),
+  ].filter(
+    (n) => !docKeywords.has(n) && !new RegExp(`\\b${n}\\b`).test(text)
+  );
+  if (unknown.length) reasons.push(`unknown names ${unknown.join(", ")}`);
+
+  for (const { heading } of getRequiredSections(node))
+    if (getDocSection(docs, heading) === undefined)
+      reasons.push(`missing # ${heading}`);
+
+  const returnType = node.field("return_type");
+  if (
+    (!returnType || returnType.text() === "()") &&
+    getDocSection(docs, "Returns") !== undefined
+  )
+    reasons.push(`# Returns without a return type`);
+  const errors = getDocSection(docs, "Errors");
+  if (errors !== undefined) {
+    const result =
+      returnType?.kind() === "generic_type" &&
+      /\bResult$/.test(returnType.field("type")?.text() || "")
+        ? returnType
+        : undefined;
+    if (!result && !/\bResult\b/.test(returnType?.text() || ""))
+      reasons.push(`# Errors without a Result`);
+    const typeArgs = result
+      ?.field("type_arguments")
+      ?.children()
+      .filter((c) => c.isNamed());
+    const errorType = typeArgs?.length === 2 ? typeArgs[1].text() : undefined;
+    const mentioned = [...errors.matchAll(/`([\w:]*Error)\b[^`]*`/g)].map(
+      (m) => m[1].split("::").pop()
+    );
+    if (
+      errorType &&
+      mentioned.length &&
+      !mentioned.some((e) => new RegExp(`\\b${e}\\b`).test(errorType))
+    )
+      reasons.push(`error type is ${errorType}`);
+  }
+  return reasons;
+}
+
+/**
+ * Collects the `cfg` predicates gating a node, from its own attributes and
+ * those of the enclosing items and file, and its own `cfg_attr` attributes.
+ */
+function getCfgConditions(node: SgNode): {
+  cfgs: string[];
+  ownCfgs: string[];
+  cfgAttrs: string[];
+} {
+  const attributes = (n: SgNode) =>
+    n.kind() === "source_file"
+      ? n.children().filter((c) => c.kind() === "inner_attribute_item")
+      : getPrecedingSiblings(n, itemPrefixKinds);
+  const predicates = (n: SgNode) =>
+    attributes(n)
+      .map((a) => cfgRegex.exec(a.text())?.[1].trim())
+      .filter(Boolean);
+  const ownCfgs = predicates(node);
+  const cfgs = [...ownCfgs];
+  for (let p = node.parent(); p; p = p.parent())
+    if (p.kind() !== "declaration_list") cfgs.push(...predicates(p));
+  const cfgAttrs = attributes(node)
+    .map((a) => cfgAttrRegex.exec(a.text())?.[1].trim())
+    .filter((a) => a && !docCfgRegex.test(a));
+  return { cfgs, ownCfgs, cfgAttrs };
+}
+
+/**
+ * Asks to mention the Cargo features and other `cfg` conditions an item
+ * depends on.
+ */
+function addCfgPrompt(_: ChatGenerationContext, node: SgNode | undefined) {
+  if (!node) return;
+  const { cfgs, cfgAttrs } = getCfgConditions(node);
+  if (!cfgs.length && !cfgAttrs.length) return;
+  const features = [
+    ...new Set(
+      cfgs.flatMap((c) => [...c.matchAll(featureRegex)].map((m) => m[1]))
+    ),
+  ];
+  const cfgRef = _.def(
+    "CFG",
+    [
+      ...cfgs.map((c) => `Compiled only when: cfg(${c})`),
+      ...cfgAttrs.map((c) => `Conditional attributes: cfg_attr(${c})`),
+    ].join("\n")
+  );
+  _.$`The declaration depends on the conditional compilation attributes listed in ${cfgRef}.`.role(
+    "system"
+  );
+  if (features.length)
+    _.$`- Note the required Cargo features in a sentence such as "Available with the ${features.map((f) => `\`${f}\``).join(" and ")} feature${features.length > 1 ? "s" : ""}."`.role(
+      "system"
+    );
+  if (cfgs.some((c) => !/^feature\s*=/.test(c)))
+    _.$`- Note the other conditions, e.g. "Only available on Unix." for cfg(unix).`.role(
+      "system"
+    );
+  if (cfgAttrs.length)
+    _.$`- Mention the behavior that depends on the cfg_attr conditions, e.g. serde support enabled by a feature.`.role(
+      "system"
+    );
+}
+
+/**
+ * Lists the generic parameters and where clause predicates of a declaration,
+ * and those inherited from its impl block or trait. Returns an empty string
+ * for non-generic declarations.
+ */
+function getGenericsSummary(node: SgNode | undefined): string {
+  const describe = (item: SgNode) => {
+    const lifetimes: string[] = [];
+    const types: string[] = [];
+    const consts: string[] = [];
+    for (const param of item.field("type_parameters")?.children() || []) {
+      const kind = param.kind();
+      if (kind.includes("lifetime")) lifetimes.push(param.text());
+      else if (kind === "const_parameter") consts.push(param.text());
+      else if (kind.endsWith("type_parameter")) types.push(param.text());
+    }
+    const predicates = (
+      item
+        .children()
+        .find((c) => c.kind() === "where_clause")
+        ?.children() || []
+    )
+      .filter((c) => c.kind() === "where_predicate")
+      .map((c) => c.text());
+    return [
+      lifetimes.length ? `Lifetime parameters: ${lifetimes.join(", ")}` : "",
+      types.length ? `Type parameters: ${types.join(", ")}` : "",
+      consts.length ? `Const parameters: ${consts.join(", ")}` : "",
+      predicates.length ? `Where clause: ${predicates.join(", ")}` : "",
+    ].filter(Boolean);
+  };
+  if (!node) return "";
+  const lines = describe(node);
+  const owner = node.parent()?.parent();
+  if (owner && ["impl_item", "trait_item"].includes(owner.kind())) {
+    const inherited = describe(owner);
+    if (inherited.length)
+      lines.push(
+        `Inherited from \`${owner.text().split(/\s*\{/)[0]}\`:`,
+        ...inherited.map((l) => `  ${l}`)
+      );
+  }
+  return lines.join("\n");
+}
+
+/**
+ * Asks to explain the non-trivial generic bounds and lifetimes in prose.
+ */
+function addGenericsPrompt(_: ChatGenerationContext, node: SgNode | undefined) {
+  const generics = getGenericsSummary(node);
+  if (!generics) return;
+  const genericsRef = _.def("GENERICS", generics);
+  _.$`The declaration is generic, its parameters and bounds are listed in ${genericsRef}.
+- Explain in prose what non-trivial bounds require from callers or implementors, e.g. which trait implementation is used for what.
+- Explain how lifetime parameters relate the inputs and outputs, e.g. which argument a returned reference borrows from.
+- Do not mention trivial bounds (Clone, Debug, Send, ...) unless they matter to the behavior, and do not restate the signature.`.role(
+    "system"
+  );
+}
+
+/**
+ * Lists the signatures of the public items declared at the top of a file.
+ */
+function getPublicItemsSummary(root: SgNode): string {
+  return root
+    .children()
+    .filter((n) =>
+      n
+        .children()
+        .some((c) => c.kind() === "visibility_modifier" && c.text() === "pub")
+    )
+    .map((n) =>
+      n
+        .text()
+        .split(/\r?\n/)[0]
+        .replace(/\s*\{\s*$/, "")
+    )
+    .join("\n");
+}
+
+/**
+ * Strips any doc comment markers the LLM may have produced and returns the
+ * plain markdown lines. Blank lines separate paragraphs and code blocks keep
+ * their indentation.
+ */
+function getDocLines(docs: string): string[] {
+  docs = docs.trim();
+  const block = /^\/\*[*!]([\s\S]*?)\*\/$/.exec(docs);
+  if (block) docs = block[1];
+  const lines = docs
+    .split(/\r?\n/g)
+    .map((line) =>
+      block
+        ? line.replace(/^\s*\*( |$)/, "")
+        : line.replace(/^\s*\/\/[/!]( |$)/, "")
+    )
+    .map((line) => line.trimEnd());
+  if (!lines.some((l) => l)) return [];
+  const indent = Math.min(
+    ...lines.filter((l) => l).map((l) => /^ */.exec(l)[0].length)
+  );
+  return lines
+    .map((line) => line.slice(indent))
+    .join("\n")
+    .replace(/\n{3,}/g, "\n\n")
+    .trim()
+    .split("\n");
+}
+
+// Markdown lines that are never merged with the surrounding text: headings,
+// tables, quotes, link definitions and HTML
+const verbatimLineRegex = /^\s*(#{1,6}\s|\||>|\[[^\]]+\]:|<)/;
+const listItemRegex = /^(\s*)([-*+]|\d+[.)])\s+/;
+// Words that start a list item, heading, quote, setext underline or HTML
+// block when they begin a line, even in the middle of a paragraph
+const blockStartRegex = /^(>|<|[-*+]$|\d{1,9}[.)]$|#{1,6}$|=+$|-+$)/;
+
+/**
+ * Splits markdown text into words, keeping inline code spans and links whole.
+ */
+function splitWords(text: string): string[] {
+  const protect = (m: string) => m.replace(/ /g, "\u0000");
+  return text
+    .replace(/(`+)[\s\S]*?\1/g, protect)
+    .replace(/\[[^\]]*\](\([^)]*\)|\[[^\]]*\])?/g, protect)
+    .split(/\s+/)
+    .filter(Boolean)
+    .map((w) => w.replace(/\u0000/g, " "));
+}
+
+/**
+ * Reflows the paragraphs and list items of markdown doc lines to `width`
+ * columns. Code blocks, headings, tables and link definitions are kept as is,
+ * and inline code spans and links are never broken.
+ */
+function wrapDocLines(lines: string[], width: number): string[] {
+  const wrapped: string[] = [];
+  let words: string[] = [];
+  let first = ""; // indentation or list marker of the first line
+  let hanging = ""; // indentation of the next lines
+  const flush = () => {
+    let line = first;
+    let count = 0; // words on the current line
+    for (const [i, word] of words.entries()) {
+      if (!count || line.length + 1 + word.length <= width) {
+        line = count ? `${line} ${word}` : line + word;
+        count++;
+      } else if (!blockStartRegex.test(word)) {
+        wrapped.push(line);
+        line = hanging + word;
+        count = 1;
+      } else if (count > 1 && !blockStartRegex.test(words[i - 1])) {
+        // move the previous word along so the marker does not start a line
+        const previous = words[i - 1];
+        wrapped.push(line.slice(0, -(previous.length + 1)));
+        line = `${hanging}${previous} ${word}`;
+        count = 2;
+      } else {
+        // rather overflow than change the rendered markdown
+        line = `${line} ${word}`;
+        count++;
+      }
+    }
+    if (count) wrapped.push(line);
+    words = [];
+  };
+
+  let fenced = false;
+  for (const line of lines) {
+    if (/^\s*(```|~~~)/.test(line)) {
+      flush();
+      fenced = !fenced;
+      wrapped.push(line);
+    } else if (
+      fenced ||
+      !line.trim() ||
+      verbatimLineRegex.test(line) ||
+      (!words.length && /^ {4}/.test(line))
+    ) {
+      flush();
+      wrapped.push(line);
+    } else {
+      const item = listItemRegex.exec(line);
+      if (item || !words.length) {
+        flush();
+        first = item ? item[0] : /^\s*/.exec(line)[0];
+        hanging = item ? " ".repeat(item[0].length) : first;
+      }
+      words.push(...splitWords(item ? line.slice(item[0].length) : line));
+    }
+  }
+  flush();
+  return wrapped;
+}
+
+/**
+ * Prefixes each doc line with a comment marker, without trailing spaces on
+ * blank lines.
+ */
+function prefixLines(prefix: string, lines: string[]): string[] {
+  return lines.map((line) => (line ? `${prefix} ${line}` : prefix));
+}
+
+/**
+ * Parses the output of `cargo test --doc` and returns the lines of the
+ * failed doctests of a file, or undefined if the tests did not run.
+ */
+function getFailedDoctestLines(
+  stdout: string,
+  testPath: string
+): number[] | undefined {
+  if (!/^test result:/m.test(stdout)) return undefined;
+  const lines: number[] = [];
+  for (const m of stdout.matchAll(
+    /^test (.+?) - .*\(line (\d+)\).*\.\.\. FAILED\s*$/gm
+  ))
+    if (m[1] === testPath) lines.push(parseInt(m[2]));
+  return lines;
+}
+
+// Names resolvable in intra-doc links without an import
+const preludeNames = new Set([
+  "bool",
+  "char",
+  "str",
+  "u8",
+  "u16",
+  "u32",
+  "u64",
+  "u128",
+  "usize",
+  "i8",
+  "i16",
+  "i32",
+  "i64",
+  "i128",
+  "isize",
+  "f32",
+  "f64",
+  "Option",
+  "Some",
+  "None",
+  "Result",
+  "Ok",
+  "Err",
+  "Vec",
+  "String",
+  "Box",
+  "Clone",
+  "Copy",
+  "Default",
+  "Drop",
+  "Eq",
+  "PartialEq",
+  "Ord",
+  "PartialOrd",
+  "Iterator",
+  "IntoIterator",
+  "Extend",
+  "From",
+  "Into",
+  "TryFrom",
+  "TryInto",
+  "AsRef",
+  "AsMut",
+  "Send",
+  "Sync",
+  "Sized",
+  "Fn",
+  "FnMut",
+  "FnOnce",
+  "ToString",
+  "ToOwned",
+  "vec",
+  "format",
+  "println",
+  "panic",
+  "assert",
+  "assert_eq",
+]);
+// Items whose members are documented with `Self::member` links
+const selfKinds = [
+  "struct_item",
+  "enum_item",
+  "union_item",
+  "trait_item",
+  "impl_item",
+];
+
+/**
+ * Normalizes an intra-doc link target to a path, dropping the disambiguator
+ * (`struct@`), generics and the `()` or `!` suffixes. Returns undefined for
+ * targets that are not paths, such as URLs and anchors.
+ */
+function getLinkPath(target: string): string | undefined {
+  const linkPath = target
+    .trim()
+    .replace(/^`|`$/g, "")
+    .replace(/^\w+@/, "")
+    .replace(/<[\s\S]*>$/, "")
+    .replace(/(\(\)|!)$/, "");
+  return /^(::)?\w+(::\w+)*$/.test(linkPath) ? linkPath : undefined;
+}
+
+/**
+ * Rust specific options, set from the script parameters.
+ */
+export interface RustOptions {
+  /** Document items of trait impls, whose docs rustdoc inherits from the trait */
+  traitImplItems?: boolean;
+  /** Document `impl` blocks, which rustdoc mostly ignores */
+  implBlocks?: boolean;
+  /** Document the `main` function of binaries */
+  main?: boolean;
+  /** Document `#[test]` and `#[bench]` functions and `#[cfg(test)]` items */
+  tests?: boolean;
+  /**
+   * Attribute patterns of the items to skip, with everything they contain,
+   * on top of the test attributes unless `tests` is set
+   */
+  skipAttributes?: string[];
+  /** Treat `pub(crate)` items as exported */
+  crateVisible?: boolean;
+  /** Generate `# Examples` sections with doctests */
+  examples?: boolean;
+  /** Run the doctests of the generated docs and drop the failing ones */
+  verifyDoctests?: boolean;
+  /** Add `#[cfg_attr(docsrs, doc(cfg(...)))]` to feature gated items of crates using it */
+  docCfg?: boolean;
+  /** Match exactly the items flagged by rustc's `missing_docs` lint */
+  missingDocs?: boolean;
+  /** Only update the docs of functions whose signature no longer matches them */
+  staleCheck?: boolean;
+}
+
+/**
+ * A `continuous-comments: ignore...` marker comment.
+ */
+interface IgnoreMarker {
+  kind: string;
+  index: number;
+  line: number;
+  /** True if the marker ends the line of the previous sibling */
+  trailing: boolean;
+}
+
+/**
+ * Comment settings read from `rustfmt.toml`.
+ */
+interface RustfmtConfig {
+  commentWidth?: number;
+  maxWidth?: number;
+  /**
+   * rustfmt only wraps comments when set, but generated docs have no line
+   * breaks of their own to preserve, so they are wrapped unless it is
+   * explicitly set to false
+   */
+  wrapComments?: boolean;
+}
 
 class Rust implements LanguageOps {
+  options: RustOptions = {};
+
+  configure(options: RustOptions) {
+    this.options = { ...this.options, ...options };
+    // missing_docs only checks the public API, without impl blocks, trait
+    // impl items or hidden items
+    if (this.options.missingDocs)
+      this.options = {
+        ...this.options,
+        traitImplItems: false,
+        implBlocks: false,
+        crateVisible: false,
+        skipAttributes: [
+          ...new Set([...(this.options.skipAttributes || []), "doc(hidden)"]),
+        ],
+      };
+    dbg(`options: %o`, this.options);
+  }
+
+  private project: CargoProject | undefined;
+  // whether cargo can run the doctests of generated docs
+  private hasCargo = false;
+  // scratch copies of the Cargo workspaces for doctests, made once per run
+  private readonly scratchDirs = new Map<
+    string,
+    Promise<string | undefined>
+  >();
+  // rustfmt settings by directory
+  private readonly rustfmt = new Map<string, RustfmtConfig | undefined>();
+
+  async loadProject(sg: AstGrep, filenames: string[]) {
+    const sources = filenames.filter((f) => f.endsWith(".rs"));
+    if (!sources.length) return;
+    for (const dir of new Set(sources.map((f) => path.dirname(f))))
+      await this.loadRustfmtConfig(dir);
+    this.project = new CargoProject(sg, this.getExportedVisibility());
+    await this.project.load(sources);
+    if (!this.project.crates.length) {
+      dbg(`no Cargo crates found`);
+      this.project = undefined;
+      return;
+    }
+    // the action image ships without a Rust toolchain
+    if (this.options.verifyDoctests) {
+      const res = await host
+        .exec("cargo", ["--version"])
+        .catch(() => undefined);
+      this.hasCargo = res?.exitCode === 0;
+      if (!this.hasCargo)
+        env.output.warn(
+          `cargo is not available, generated doctests are not verified`
+        );
+    }
+  }
+
+  /**
+   * Finds the nearest `rustfmt.toml` or `.rustfmt.toml`, as rustfmt does.
+   */
+  private async loadRustfmtConfig(
+    dir: string
+  ): Promise<RustfmtConfig | undefined> {
+    if (this.rustfmt.has(dir)) return this.rustfmt.get(dir);
+    let config: RustfmtConfig | undefined;
+    for (const name of ["rustfmt.toml", ".rustfmt.toml"]) {
+      const text = await readText(path.join(dir, name));
+      if (text === undefined) continue;
+      const toml = parsers.TOML(text) || {};
+      config = {
+        commentWidth: toml.comment_width,
+        maxWidth: toml.max_width,
+        wrapComments: toml.wrap_comments,
+      };
+      dbg(`rustfmt config %s: %o`, path.join(dir, name), config);
+      break;
+    }
+    const parent = path.dirname(dir);
+    if (!config && parent !== dir)
+      config = await this.loadRustfmtConfig(parent);
+    this.rustfmt.set(dir, config);
+    return config;
+  }
+
+  private getRustfmtConfig(decl: SgNode): RustfmtConfig | undefined {
+    return this.rustfmt.get(path.dirname(decl.getRoot().filename()));
+  }
+
+  /**
+   * Returns the width available to the text of the docs of a declaration:
+   * rustfmt's `comment_width` (capped by `max_width`), minus the indentation
+   * and the comment marker.
+   */
+  private getCommentWidth(decl: SgNode, style: DocStyle): number {
+    const config = this.getRustfmtConfig(decl);
+    const limit = Math.min(config?.commentWidth ?? 80, config?.maxWidth ?? 100);
+    const indent =
+      decl.kind() === "source_file"
+        ? 0
+        : (
+            getInlineModuleDocs(decl)[0] || this.getCommentInsertionNode(decl)
+          ).range().start.column;
+    const marker =
+      style === "attribute"
+        ? '#[doc = " "]'.length
+        : style === "block"
+          ? " * ".length
+          : "/// ".length;
+    return Math.max(limit - indent - marker, 20);
+  }
+
+  /**
+   * Drops the items outside of the public API of the crate when `exportsOnly`
+   * is set, and moves the public API first so it gets the edit budget.
+   */
+  prioritizeMatches(matches: SgNode[], filename: string, exportsOnly: boolean) {
+    if (!this.project) return matches;
+    exportsOnly ||= this.options.missingDocs;
+    // a file module documented on its `mod` declaration needs no inner docs
+    if (this.project.getModule(filename)?.documented)
+      matches = matches.filter(
+        (m) => m.kind() !== "source_file" || this.getCommentNodes(m)
+      );
+    const ranked = matches.map((match) => ({
+      match,
+      public:
+        match.kind() === "source_file"
+          ? this.project.isPublicApi(match, filename) ?? true
+          : // outside of a crate, the matcher already checked the item itself
+            this.project.isPublicApi(match, filename) ??
+            (match.kind() === "macro_definition"
+              ? isMacroExported(match)
+              : getModules(match).every((m) => this.isExported(m))),
+    }));
+    // inner docs come first in the file, keep them in front
+    const header = ranked.filter((r) => r.match.kind() === "source_file");
+    const items = ranked.filter((r) => r.match.kind() !== "source_file");
+    return [
+      ...header,
+      ...items.filter((r) => r.public),
+      ...items.filter((r) => !r.public),
+    ]
+      .filter((r) => !exportsOnly || r.public)
+      .map((r) => r.match);
+  }
+
+  /**
+   * Copies a Cargo workspace to a scratch directory for doctests, or returns
+   * undefined if the copy fails.
+   */
+  private async copyWorkspace(
+    workspaceDir: string,
+    name: string
+  ): Promise<string | undefined> {
+    const scratchDir = path.resolve(".genaiscript", "doctests", name);
+    const archive = `${scratchDir}.tar`;
+    const excludes = ["target", ".git", ".genaiscript", "node_modules"];
+    // no shell, so paths are passed to each command as is
+    const copy: [string, string[]][] = [
+      ["rm", ["-rf", scratchDir, archive]],
+      ["mkdir", ["-p", scratchDir]],
+      [
+        "tar",
+        [
+          "-C",
+          workspaceDir,
+          ...excludes.map((e) => `--exclude=./${e}`),
+          "-cf",
+          archive,
+          ".",
+        ],
+      ],
+      ["tar", ["-C", scratchDir, "-xf", archive]],
+    ];
+    for (const [command, args] of copy) {
+      const res = await host.exec(command, args);
+      if (res.exitCode) {
+        env.output.warn(`failed to copy ${workspaceDir} to verify doctests`);
+        dbg(`%s`, res.stderr);
+        return undefined;
+      }
+    }
+    dbg(`copied %s to %s`, workspaceDir, scratchDir);
+    return scratchDir;
+  }
+
+  /**
+   * Runs `cargo test --doc` on a scratch copy of the workspace, made once per
+   * run, with the candidate file swapped in, and rejects the docs whose
+   * doctests fail. Docs are kept when they cannot be checked, e.g. outside of
+   * a lib crate or when the crate does not build offline or cargo is not
+   * installed.
+   */
+  async verifyDocs(filename: string, content: string, docs: string[]) {
+    const accepted = docs.map(() => true);
+    if (!this.options.verifyDoctests || !this.project || !this.hasCargo)
+      return accepted;
+    if (!docs.some((d) => d.includes("```"))) return accepted;
+    const module = this.project.getModule(filename);
+    if (!module?.crate.lib) {
+      dbg(`%s is not part of a lib crate, skipping doctests`, filename);
+      return accepted;
+    }
+
+    // run on a copy of the workspace so path dependencies and inherited
+    // fields resolve, with the candidate content swapped in for the test
+    const { crate } = module;
+    const workspaceDir = await this.project.getWorkspaceDir(crate);
+    let scratch = this.scratchDirs.get(workspaceDir);
+    if (!scratch) {
+      scratch = this.copyWorkspace(workspaceDir, crate.name);
+      this.scratchDirs.set(workspaceDir, scratch);
+    }
+    const scratchDir = await scratch;
+    if (!scratchDir) return accepted;
+    const scratchFile = path.join(
+      scratchDir,
+      path.relative(workspaceDir, filename)
+    );
+    const original = (await workspace.readText(scratchFile))?.content;
+    await workspace.writeText(scratchFile, content);
+
+    const testPath = path.relative(crate.dir, filename);
+    let res: ShellOutput;
+    try {
+      res = await host.exec(
+        "cargo",
+        [
+          "test",
+          "--doc",
+          "--offline",
+          "--target-dir",
+          path.resolve(".genaiscript", "doctests", "target"),
+          "--",
+          testPath,
+        ],
+        { cwd: path.join(scratchDir, path.relative(workspaceDir, crate.dir)) }
+      );
+    } finally {
+      // the copy is shared by the next files
+      if (original !== undefined)
+        await workspace.writeText(scratchFile, original);
+    }
+    const failed = getFailedDoctestLines(res.stdout || "", testPath);
+    if (!failed) {
+      env.output.warn(`cargo test --doc did not run for ${crate.name}`);
+      dbg(`%s`, res.stderr);
+      return accepted;
+    }
+    dbg(`failed doctests in %s at lines %o`, filename, failed);
+
+    // locate each doc in the content, in order, to map failures to docs
+    let offset = 0;
+    return docs.map((doc) => {
+      const index = content.indexOf(doc, offset);
+      if (index < 0) return true;
+      offset = index + doc.length;
+      const first = content.slice(0, index).split("\n").length;
+      const last = first + doc.split("\n").length - 1;
+      return !failed.some((line) => line >= first && line <= last);
+    });
+  }
+
+  /**
+   * Returns a resolver for the intra-doc links in the docs of a declaration.
+   * It returns the link path if it resolves in scope, a path that resolves
+   * if the item is declared elsewhere in the crate, or null if the link
+   * cannot be resolved.
+   */
+  private getLinkResolver(decl: SgNode): (linkPath: string) => string | null {
+    const root = decl.getRoot();
+    const module = this.project?.getModule(root.filename());
+    const mods = getModules(decl);
+    const localPath = mods.map((m) => m.field("name")?.text());
+    const modPath = [...(module?.path || []), ...localPath];
+    // outside of a crate, only the items of the file are known
+    let symbols = module?.crate.symbols;
+    if (!symbols) {
+      symbols = new Set();
+      collectSymbols(root.root(), [], symbols);
+    }
+
+    // names imported in the enclosing scopes
+    const imported = new Set<string>();
+    for (const scope of [root.root(), ...mods.map((m) => m.field("body"))])
+      for (const node of scope?.children() || [])
+        if (node.kind() === "use_declaration")
+          for (const name of getImportedNames(
+            node.field("argument")?.text() || ""
+          ))
+            imported.add(name);
+
+    let selfNode: SgNode | undefined = decl;
+    while (selfNode && !selfKinds.includes(selfNode.kind()))
+      selfNode = selfNode.parent();
+    const selfName =
+      selfNode?.kind() === "impl_item"
+        ? getImplTypeName(selfNode)
+        : selfNode?.field("name")?.text();
+
+    return (linkPath) => {
+      const segments = linkPath.replace(/^::/, "").split("::");
+      const [first] = segments;
+      // standard and dependency crates are in the extern prelude
+      if (
+        ["std", "core", "alloc"].includes(first) ||
+        module?.crate.dependencies.has(first) ||
+        preludeNames.has(first)
+      )
+        return linkPath;
+      if (imported.has(first)) return linkPath;
+
+      // path from the crate root, or from the file outside of a crate
+      let absolute: string[];
+      if (first === "crate") absolute = segments.slice(1);
+      else if (first === "self") absolute = [...modPath, ...segments.slice(1)];
+      else if (first === "super") {
+        const base = [...modPath];
+        while (segments[0] === "super") {
+          segments.shift();
+          base.pop();
+        }
+        absolute = [...base, ...segments];
+      } else if (first === "Self")
+        absolute = [...modPath, selfName, ...segments.slice(1)];
+      else absolute = [...modPath, ...segments];
+      if (symbols.has(absolute.join("::"))) return linkPath;
+      // glob imports may bring the item in scope
+      if (imported.has("*")) return linkPath;
+
+      // look the item up by its trailing segments elsewhere
+      const suffix = segments
+        .filter((s) => !["crate", "self", "super", "Self"].includes(s))
+        .join("::");
+      const candidates = [...symbols].filter(
+        (s) => s === suffix || s.endsWith(`::${suffix}`)
+      );
+      if (candidates.length !== 1) return null;
+      return module
+        ? `crate::${candidates[0]}`
+        : [...localPath.map(() => "super"), candidates[0]].join("::");
+    };
+  }
+
+  /**
+   * Rewrites the intra-doc links of generated docs to resolvable paths, and
+   * strips the link markup of links that cannot be resolved, which would
+   * otherwise trigger `rustdoc::broken_intra_doc_links`.
+   */
+  private repairLinks(docs: string, decl: SgNode): string {
+    const resolve = this.getLinkResolver(decl);
+    const repair = (target: string): string | null => {
+      const linkPath = getLinkPath(target);
+      if (!linkPath) return target;
+      const resolved = resolve(linkPath);
+      if (resolved === null) dbg(`broken intra-doc link: %s`, target);
+      else if (resolved !== linkPath)
+        dbg(`intra-doc link %s resolved to %s`, target, resolved);
+      return resolved === linkPath ? target : resolved;
+    };
+
+    const lines = docs.split("\n");
+    const labels = new Set(
+      lines
+        .map((line) => /^\s*\[([^\]]+)\]:/.exec(line)?.[1].toLowerCase())
+        .filter(Boolean)
+    );
+    let fenced = false;
+    return lines
+      .map((line) => {
+        if (/^\s*(```|~~~)/.test(line)) fenced = !fenced;
+        if (fenced || /^\s*(```|~~~)/.test(line)) return line;
+
+        // reference definitions: [label]: target
+        const definition = /^(\s*\[[^\]]+\]:\s*)(\S+)\s*$/.exec(line);
+        if (definition) {
+          const target = repair(definition[2]);
+          return target === null ? undefined : definition[1] + target;
+        }
+        return (
+          line
+            // inline links: [text](target)
+            .replace(/\[([^\]]+)\]\(([^)\s]+)\)/g, (m, text, target) => {
+              const repaired = repair(target);
+              return repaired === null ? text : `[${text}](${repaired})`;
+            })
+            // reference links: [text][target], [text][]
+            .replace(/\[([^\]]+)\]\[([^\]]*)\]/g, (m, text, label) => {
+              const target = label || text;
+              if (labels.has(target.toLowerCase())) return m;
+              const repaired = repair(target);
+              return repaired === null
+                ? text
+                : repaired === target
+                  ? m
+                  : `[${text}](${repaired})`;
+            })
+            // shortcut links: [`path`]
+            .replace(/\[(`[^`\]]+`)\](?![(\[:])/g, (m, code) => {
+              if (labels.has(code.toLowerCase())) return m;
+              const repaired = repair(code);
+              return repaired === null
+                ? code
+                : repaired === code
+                  ? m
+                  : `[${code}](${repaired})`;
+            })
+        );
+      })
+      .filter((line) => line !== undefined)
+      .join("\n");
+  }
+
+  private getExportedVisibility() {
+    return this.options.crateVisible
+      ? /^(pub(\s*\(\s*crate\s*\))?|crate)$/
+      : /^pub$/;
+  }
+
+  private isExported(node: SgNode) {
+    const visibility = node
+      .children()
+      .find((c) => c.kind() === "visibility_modifier");
+    return !!visibility && this.getExportedVisibility().test(visibility.text());
+  }
+
   getCommentableNodesMatcher(
     entityKinds: EntityKind[],
     withComments: boolean,
     exportsOnly: boolean
   ) {
+    if (this.options.missingDocs) {
+      entityKinds = ["module", "type", "function", "property", "variable"];
+      exportsOnly = true;
+    }
     const declKindsRaw: SgRule = {
       any: [
         // Functions
         entityKinds.includes("function") ? { kind: "function_item" } : null,
-        // Types (structs, enums, type aliases, traits)
+        // Macros (macro_rules!), proc macros are functions
+        entityKinds.includes("function") ? { kind: "macro_definition" } : null,
+        // Types (structs, enums, unions, type aliases, traits)
         entityKinds.includes("type") ? { kind: "struct_item" } : null,
         entityKinds.includes("type") ? { kind: "enum_item" } : null,
+        entityKinds.includes("type") ? { kind: "union_item" } : null,
         entityKinds.includes("type") ? { kind: "type_item" } : null,
         entityKinds.includes("type") ? { kind: "trait_item" } : null,
-        entityKinds.includes("type") ? { kind: "impl_item" } : null,
-        // Module items
+        entityKinds.includes("type") && this.options.implBlocks
+          ? { kind: "impl_item" }
+          : null,
+        // Module items and extern blocks
         entityKinds.includes("module") ? { kind: "mod_item" } : null,
+        entityKinds.includes("module") ? { kind: "foreign_mod_item" } : null,
         // Constants and static variables
         entityKinds.includes("variable") ? { kind: "const_item" } : null,
         entityKinds.includes("variable") ? { kind: "static_item" } : null,
       ].filter(Boolean) as SgRule[],
     };
 
-    // Rust has public/private visibility, so we can filter by pub if exportsOnly is true
+    // Rust has public/private visibility, so we can filter by pub if exportsOnly is true.
+    // Only bare `pub` is part of the public API, `pub(crate)` is opt-in and
+    // `pub(super)` or `pub(in path)` never are.
+    const visibility: SgRule = { kind: "visibility_modifier" };
+    const pub: SgRule = {
+      ...visibility,
+      regex: this.getExportedVisibility().source,
+    };
+    // An item is only reachable if every enclosing module is exported too,
+    // unless the Cargo project tells otherwise through `pub use` re-exports
+    // Exported macros live at the crate root, whatever their module
+    const exportedMacro: SgRule = {
+      kind: "macro_definition",
+      ...followsAttribute(macroExportRegex),
+    };
+    const reachable: SgRule = {
+      any: [
+        exportedMacro,
+        {
+          not: {
+            inside: { kind: "mod_item", not: { has: pub }, stopBy: "end" },
+          },
+        },
+      ],
+    };
     const declKinds: SgRule = exportsOnly
       ? {
           any: [
             {
               any: declKindsRaw.any,
-              has: { kind: "visibility_modifier" },
+              has: pub,
             },
-          ],
+            entityKinds.includes("function") ? exportedMacro : null,
+          ].filter(Boolean) as SgRule[],
         }
       : {
           any: [declKindsRaw],
@@ -46,75 +1500,535 @@ class Rust implements LanguageOps {
       inside: {
         any: [
           { kind: "source_file" }, // Rust files have source_file as root
-          { kind: "mod_item" }, // Or inside modules
-          { kind: "impl_item" }, // Or inside impl blocks
+          {
+            kind: "declaration_list", // Or inside modules and impl blocks
+            inside: {
+              any: [
+                { kind: "mod_item" },
+                this.options.traitImplItems
+                  ? { kind: "impl_item" }
+                  : { kind: "impl_item", not: { has: traitImplRule } },
+              ],
+            },
+          },
         ],
       },
     };
 
+    // Trait items: required and provided methods, associated types and consts.
+    // They have no visibility of their own and are as visible as the trait.
+    const traitItemKinds: SgRule = {
+      any: [
+        entityKinds.includes("function") ? { kind: "function_item" } : null,
+        entityKinds.includes("function")
+          ? { kind: "function_signature_item" }
+          : null,
+        entityKinds.includes("type") ? { kind: "associated_type" } : null,
+        // like the consts of inherent impls and modules
+        entityKinds.includes("variable") ? { kind: "const_item" } : null,
+      ].filter(Boolean) as SgRule[],
+      inside: {
+        kind: "declaration_list",
+        inside: exportsOnly
+          ? { kind: "trait_item", has: pub }
+          : { kind: "trait_item" },
+      },
+    };
+
+    // Items of extern blocks: foreign functions and statics
+    const foreignItemKinds: SgRule = {
+      any: [
+        entityKinds.includes("function")
+          ? { kind: "function_signature_item" }
+          : null,
+        entityKinds.includes("variable") ? { kind: "static_item" } : null,
+      ].filter(Boolean) as SgRule[],
+      ...(exportsOnly ? { has: pub } : {}),
+      inside: {
+        kind: "declaration_list",
+        inside: { kind: "foreign_mod_item" },
+      },
+    };
+
+    // Struct fields and enum variants. Tuple struct fields have no node of
+    // their own, so they are anchored on their visibility or type. Docs can
+    // only go above fields on their own line, so one-line field lists such as
+    // `struct Meters(pub f64);` are left alone.
+    const fieldRule: SgRule = {
+      kind: "field_declaration",
+      inside: { kind: "field_declaration_list" },
+    };
+    const tupleFieldList: SgRule = {
+      kind: "ordered_field_declaration_list",
+      regex: "\\n",
+    };
+    const visibleTupleFieldRule: SgRule = {
+      ...visibility,
+      inside: tupleFieldList,
+    };
+    const tupleFieldRule: SgRule = {
+      any: [
+        visibleTupleFieldRule,
+        {
+          regex: ".",
+          inside: { ...tupleFieldList, field: "type" },
+          not: { follows: { ...visibility, stopBy: "neighbor" } },
+        },
+      ],
+    };
+    const variantRule: SgRule = { kind: "enum_variant" };
+    // missing_docs does not check tuple fields
+    const propertyKindsRaw: SgRule[] = entityKinds.includes("property")
+      ? this.options.missingDocs
+        ? [fieldRule, variantRule]
+        : [fieldRule, tupleFieldRule, variantRule]
+      : [];
+    // Fields are exported if they and their struct are. Enum variants and
+    // their fields are as visible as the enum.
+    const pubStruct: SgRule = {
+      any: [{ kind: "struct_item" }, { kind: "union_item" }],
+      has: pub,
+      stopBy: "end",
+    };
+    const propertyKinds: SgRule = exportsOnly
+      ? {
+          any: [
+            { ...fieldRule, has: pub, inside: pubStruct },
+            {
+              ...pub,
+              inside: { ...tupleFieldList, inside: pubStruct },
+            },
+            {
+              any: propertyKindsRaw,
+              inside: { kind: "enum_item", has: pub, stopBy: "end" },
+            },
+          ],
+        }
+      : {
+          any: propertyKindsRaw,
+        };
+
+    // Look past the attribute stack (and stray comments) for the doc comment
     const withDocComment: SgRule = {
       follows: {
-        kind: "line_comment",
-        regex: "^///",
-        stopBy: "neighbor",
+        ...outerDocRule,
+        stopBy: { not: itemPrefixRule },
+      },
+    };
+    const withIncludedDoc: SgRule = {
+      follows: {
+        ...includedDocRule,
+        stopBy: { not: itemPrefixRule },
       },
     };
 
+    // Inline modules can also be documented by inner docs in their body
+    const withModuleInnerDocs: SgRule = {
+      kind: "mod_item",
+      has: { field: "body", has: innerDocRule },
+    };
+
     const docsRule: SgRule = withComments
-      ? withDocComment
+      ? {
+          any: [
+            { all: [withDocComment, { not: withIncludedDoc }] },
+            withModuleInnerDocs,
+          ],
+        }
       : {
           not: withDocComment,
         };
 
-    return { ...declKinds, ...inside, ...docsRule };
+    // The file itself is documented with inner docs (//!) at the top
+    const withInnerDocComment: SgRule = {
+      has: innerDocRule,
+    };
+    const fileRule: SgRule = {
+      kind: "source_file",
+      ...(withComments
+        ? { all: [withInnerDocComment, { not: { has: includedDocRule } }] }
+        : { not: withInnerDocComment }),
+    };
+
+    // Items that are not worth documenting unless opted in: hidden and
+    // test-only items, and everything declared inside them
+    const skippedAttributes = [
+      ...new Set([
+        ...(this.options.tests ? [] : testAttributes),
+        ...(this.options.skipAttributes || []).filter(
+          (pattern) => !this.options.tests || !testAttributes.includes(pattern)
+        ),
+      ]),
+    ];
+    const skippedItems: SgRule[] = skippedAttributes.map((pattern) =>
+      followsAttribute(getAttributeRegex(pattern))
+    );
+    const skipped: SgRule[] = [
+      this.options.main
+        ? null
+        : {
+            kind: "function_item",
+            has: { field: "name", regex: "^main$" },
+            inside: { kind: "source_file" },
+          },
+      ...skippedItems,
+      ...skippedItems.map((rule) => ({ inside: { ...rule, stopBy: "end" } })),
+      // Modules get their docs in one place, as rustdoc joins outer and inner
+      // docs: in the body of inline modules that have inner docs, and in the
+      // files of other modules rather than on their declarations
+      withComments
+        ? null
+        : {
+            kind: "mod_item",
+            any: [
+              withModuleInnerDocs,
+              { not: { has: { field: "body", kind: "declaration_list" } } },
+            ],
+          },
+    ].filter(Boolean) as SgRule[];
+
+    const itemKinds: SgRule[] = [
+      { ...declKinds, ...inside },
+      traitItemKinds,
+      foreignItemKinds,
+      entityKinds.includes("property") ? propertyKinds : null,
+    ].filter(Boolean) as SgRule[];
+
+    return {
+      any: [
+        {
+          any: itemKinds,
+          all: [
+            docsRule,
+            exportsOnly && !this.project ? reachable : null,
+            skipped.length ? { not: { any: skipped } } : null,
+          ].filter(Boolean) as SgRule[],
+        },
+        entityKinds.includes("module") ? fileRule : null,
+      ].filter(Boolean) as SgRule[],
+    };
   }
 
   getCommentNodes(node: SgNode) {
-    const commentNodes = [];
-    let current = node.prev();
-    // Collect all preceding doc comment nodes (///)
-    while (
-      current &&
-      current.kind() === "line_comment" &&
-      current.text().startsWith("///")
-    ) {
-      commentNodes.unshift(current); // Add to beginning to maintain order
-      current = current.prev();
+    // Collect all preceding doc comment nodes (///, /** */, #[doc = ...]),
+    // including the ones above or between attributes.
+    // Files collect their inner doc comment nodes (//!, /*! */, #![doc = ...])
+    const inner = node.kind() === "source_file";
+    let commentNodes = (
+      inner ? getFileHeader(node) : getPrecedingSiblings(node, itemPrefixKinds)
+    ).filter((n) => getDocStyle(n, inner));
+    // or else the inner docs in the body of inline modules
+    if (!commentNodes.length) commentNodes = getInlineModuleDocs(node);
+    if (commentNodes.some((n) => includedDocRegex.test(n.text()))) {
+      dbg(`docs included from another file, skipping`);
+      return null;
     }
     return commentNodes.length > 0 ? commentNodes : null;
   }
 
   getCommentInsertionNode(node: SgNode) {
-    return node;
+    // Inner docs go after any license header, above the inner attributes
+    // and the first item with its docs and comments
+    if (node.kind() === "source_file") {
+      const children = node.children();
+      let end = children.findIndex(
+        (n) =>
+          !["shebang", "line_comment", "block_comment"].includes(n.kind()) ||
+          getDocStyle(n)
+      );
+      if (end < 0) return node;
+      // comments right above the first item belong to it
+      while (
+        end > 0 &&
+        ["line_comment", "block_comment"].includes(children[end - 1].kind()) &&
+        children[end].range().start.line - getEndLine(children[end - 1]) <= 1
+      )
+        end--;
+      return children[end];
+    }
+
+    // Insert above the attribute stack, where rustfmt places docs
+    const attributes = getPrecedingSiblings(node, attributeKinds);
+    dbg(`attributes before %s: %d`, node.kind(), attributes.length);
+    return attributes[0] || node;
   }
 
   getLanguageSystemPromptName() {
     return "";
   }
 
-  getCommentText(docs: string) {
-    docs = parsers.unfence(docs, "/");
+  /**
+   * Skips the items with an `ignore-next` marker above them, or an `ignore`
+   * marker at the end of their first line, with everything they contain, and
+   * all the items of files with an `ignore-file` marker.
+   */
+  isIgnored(node: SgNode) {
+    const markers = this.getIgnoreMarkers(node.getRoot().root());
+    if (!markers.length) return false;
+    if (markers.some((m) => m.kind === "ignore-file")) {
+      dbg(`file ignored`);
+      return true;
+    }
+    for (let n = node; n && n.kind() !== "source_file"; n = n.parent()) {
+      const prefix = getPrecedingSiblings(n, itemPrefixKinds);
+      const above = new Set(prefix.map((p) => p.range().start.index));
+      // the first line of an item may be one of its attributes
+      const lines = new Set(
+        [n, ...prefix.filter((p) => attributeKinds.includes(p.kind()))].map(
+          (p) => p.range().start.line
+        )
+      );
+      if (
+        markers.some((m) =>
+          m.kind === "ignore"
+            ? lines.has(m.line)
+            : m.kind === "ignore-next" && !m.trailing && above.has(m.index)
+        )
+      ) {
+        dbg(`ignored %s`, n.text().split("\n")[0]);
+        return true;
+      }
+    }
+    return false;
+  }
+
+  // ignore markers of the last file, found once for all its matches
+  private ignoreMarkers: { text: string; markers: IgnoreMarker[] } | undefined;
+
+  private getIgnoreMarkers(root: SgNode): IgnoreMarker[] {
+    const text = root.text();
+    if (this.ignoreMarkers?.text !== text)
+      this.ignoreMarkers = {
+        text,
+        markers: root
+          .findAll({
+            rule: {
+              any: [{ kind: "line_comment" }, { kind: "block_comment" }],
+              regex: ignoreMarkerRegex.source,
+            },
+          })
+          .map((m) => {
+            const line = m.range().start.line;
+            const prev = m.prev();
+            return {
+              kind: ignoreMarkerRegex.exec(m.text())[1],
+              index: m.range().start.index,
+              line,
+              // at the end of the line of the previous item
+              trailing: !!prev && getEndLine(prev) === line,
+            };
+          }),
+      };
+    return this.ignoreMarkers.markers;
+  }
+
+  /**
+   * Checks the docs of functions against their signature, other items are
+   * always sent to the LLM.
+   */
+  isDocStale(declNode: SgNode, docNodes: SgNode[]) {
+    const kind = declNode.kind();
+    if (
+      !this.options.staleCheck ||
+      (kind !== "function_item" && kind !== "function_signature_item")
+    )
+      return true;
+    const docs = getDocNodesText(docNodes);
+    if (docs === undefined) return true;
+    const reasons = getStaleDocsReasons(declNode, docs);
+    dbg(`stale docs: %o`, reasons);
+    return reasons.length > 0;
+  }
 
-    // Check if it's already properly formatted Rust doc comments
-    if (/^\/\/\//.test(docs.trim())) {
-      return docs;
+  addJudgePrompt(_: ChatGenerationContext, declNode: SgNode, docsRef: string) {
+    const sections = getRequiredSections(declNode);
+    if (sections.length)
+      _.$`Rust conventions require ${docsRef} to contain the following sections:
+${sections.map(({ heading, reason }) => `- \`# ${heading}\`: ${reason}.`).join("\n")}
+Documentation missing one of these sections is not acceptable. Adding a missing section is a significant improvement.`;
+    const generics = getGenericsSummary(declNode);
+    if (generics) {
+      const genericsRef = _.def("GENERICS", generics);
+      _.$`The generic parameters and bounds of the declaration are listed in ${genericsRef}. Documentation describing constraints on type parameters or lifetimes that do not match ${genericsRef} is not acceptable.`;
     }
+  }
+
+  getCommentText(docs: string, decl?: SgNode) {
+    docs = parsers.unfence(docs, "/");
+    let lines = getDocLines(docs);
+    if (decl) lines = this.repairLinks(lines.join("\n"), decl).split("\n");
+
+    // Format in the style already used by the item, or else by the file
+    const inner =
+      decl?.kind() === "source_file" || getInlineModuleDocs(decl).length > 0;
+    const style = decl ? this.getPreferredDocStyle(decl) : "line";
+    dbg(`doc style: %s (inner: %s)`, style, inner);
+    if (decl && this.getRustfmtConfig(decl)?.wrapComments !== false)
+      lines = wrapDocLines(lines, this.getCommentWidth(decl, style));
+    const comment = this.formatComment(lines, style, inner);
+    // inner docs are separated from the first item by a blank line
+    if (inner) return `${comment}\n\n`;
+    const docCfg = decl && this.getDocCfgAttribute(decl);
+    return docCfg ? `${comment}\n${docCfg}` : comment;
+  }
+
+  /**
+   * Returns the `#[cfg_attr(docsrs, doc(cfg(...)))]` attribute to add to a
+   * feature gated item, if enabled and the crate already uses the docs.rs
+   * convention, and the item does not have one yet.
+   */
+  private getDocCfgAttribute(decl: SgNode): string | undefined {
+    if (!this.options.docCfg || decl.kind() === "source_file")
+      return undefined;
+    const root = decl.getRoot();
+    const module = this.project?.getModule(root.filename());
+    const docsrs = module
+      ? module.crate.docsrs
+      : /\bcfg_attr\s*\(\s*docsrs\b/.test(root.root().text());
+    if (!docsrs) return undefined;
+    if (
+      getPrecedingSiblings(decl, itemPrefixKinds).some((a) =>
+        docCfgRegex.test(a.text())
+      )
+    )
+      return undefined;
+    const features = getCfgConditions(decl).ownCfgs.filter((c) =>
+      /\bfeature\s*=/.test(c)
+    );
+    if (!features.length) return undefined;
+    const cfg =
+      features.length > 1 ? `all(${features.join(", ")})` : features[0];
+    return `#[cfg_attr(docsrs, doc(cfg(${cfg})))]`;
+  }
+
+  private formatComment(lines: string[], style: DocStyle, inner: boolean) {
+    if (style === "block")
+      return [inner ? "/*!" : "/**", ...prefixLines(" *", lines), " */"].join(
+        "\n"
+      );
+    if (style === "attribute")
+      return lines
+        .map(
+          (line) =>
+            `${inner ? "#!" : "#"}[doc = "${line ? " " : ""}${line.replace(/\\/g, "\\\\").replace(/"/g, '\\"')}"]`
+        )
+        .join("\n");
+    return prefixLines(inner ? "//!" : "///", lines).join("\n");
+  }
 
-    // Format as Rust doc comments
-    const lines = docs
-      .split(/\r?\n/g)
-      .map((line) => line.trim())
-      .filter((line) => line);
+  /**
+   * Asks for a `# Examples` section with a doctest that uses the public path
+   * of the item, for public functions and types.
+   */
+  private addExamplesPrompt(
+    _: ChatGenerationContext,
+    node: SgNode | undefined,
+    filename: string | undefined
+  ) {
+    if (!node || !exampleKinds.includes(node.kind())) return;
+    if (filename && this.project?.isPublicApi(node, filename) === false) {
+      dbg(`not public, skipping examples`);
+      return;
+    }
+    const itemPath = filename && this.project?.getItemPath(node, filename);
+    _.$`- Add a \`# Examples\` section with a fenced \`\`\`rust doctest showing typical usage.
+- The doctest is compiled as an external crate: import the item with its public path${itemPath ? ` (\`use ${itemPath};\`)` : ""}, never with \`crate::\`.
+- Hide setup lines with a leading \`# \` and use \`?\` with a hidden \`fn main() -> Result<..>\` wrapper for fallible calls.
+- Use \`\`\`no_run when the example performs I/O (files, network, processes) or blocks; use \`\`\`ignore only if it cannot compile on its own.`.role(
+      "system"
+    );
+    if (ioRegex.test(node.text()))
+      _.$`- The ${node.kind()} performs I/O, mark the doctest as \`\`\`no_run.`.role(
+        "system"
+      );
+  }
 
-    return lines.map((line) => `/// ${line}`).join("\n");
+  private getPreferredDocStyle(decl: SgNode): DocStyle {
+    const docNode =
+      this.getCommentNodes(decl)?.[0] ||
+      decl.getRoot().root().find({ rule: outerDocRule });
+    return (
+      (docNode && (getDocStyle(docNode) || getDocStyle(docNode, true))) ||
+      "line"
+    );
   }
 
   addGenerateDocPrompt(
     _: ChatGenerationContext,
     declKind: any,
     declRef: string,
-    fileRef: string
+    fileRef: string,
+    declNode?: SgNode,
+    filename?: string
   ) {
+    if (declKind === "source_file") {
+      const itemsRef = _.def(
+        "PUBLIC_ITEMS",
+        getPublicItemsSummary(declNode) || "(no public items)"
+      );
+      return _.$`Generate a Rust inner documentation comment for the crate or module in ${fileRef}.
+- Start with a one sentence summary of the purpose of the crate or module.
+- Describe the main public items listed in ${itemsRef} and how they fit together.
+- Be concise. Use a technical tone.
+- Use Rust inner doc comment syntax (//!).
+- Use standard Rust documentation conventions.`;
+    }
+    if (isProperty(declNode)) {
+      const parentRef = _.def("PARENT", getPropertyParent(declNode).text(), {
+        flex: 5,
+      });
+      // the declaration of a tuple field is only its visibility or type
+      const tupleField = getTupleFieldText(declNode);
+      if (tupleField)
+        return _.$`Generate a Rust documentation comment for the tuple field \`${tupleField}\` declared in ${parentRef}.
+- Describe what the field represents and any invariants, in one or two sentences.
+- Do NOT restate its type.
+- Use Rust doc comment syntax with triple slashes (///).
+- Use standard Rust documentation conventions.
+The full source of the file is in ${fileRef} for reference.`;
+      return _.$`Generate a Rust documentation comment for the field or variant ${declRef} declared in ${parentRef}.
+- Describe what the field or variant represents and any invariants, in one or two sentences.
+- Do NOT restate its type.
+- Use Rust doc comment syntax with triple slashes (///).
+- Use standard Rust documentation conventions.
+The full source of the file is in ${fileRef} for reference.`;
+    }
+    const macroKind = getMacroKind(declNode);
+    if (macroKind === "declarative macro") {
+      const armsRef = _.def(
+        "MATCHER_ARMS",
+        declNode
+          .children()
+          .filter((c) => c.kind() === "macro_rule")
+          .map((c) => c.field("left")?.text())
+          .filter(Boolean)
+          .join("\n")
+      );
+      const itemPath = filename && this.project?.getItemPath(declNode, filename);
+      return _.$`Generate a Rust documentation comment for the macro_rules! macro ${declRef}.
+- Start with a one sentence summary of what the macro does.
+- Document each matcher arm listed in ${armsRef}: the syntax it accepts and what it expands to.
+- Add an \`# Examples\` section with a fenced \`\`\`rust block showing an invocation of each arm.
+${isMacroExported(declNode) ? `- The doctest is compiled as an external crate: import the macro with ${itemPath ? `\`use ${itemPath};\`` : "its crate name"}.` : "- The macro is not exported with #[macro_export], so mark the example \`\`\`ignore."}
+- Be concise. Use a technical tone.
+- Use Rust doc comment syntax with triple slashes (///).
+The full source of the file is in ${fileRef} for reference.`;
+    }
+    if (macroKind)
+      return _.$`Generate a Rust documentation comment for the ${macroKind} ${declRef}.
+- Start with a one sentence summary of the code the macro generates.
+- Describe the input it accepts: the supported items and helper attributes for a derive macro, the arguments and annotated item for an attribute macro, the token syntax for a function-like macro.
+- Describe the compile errors reported for invalid input.
+- Add an \`# Examples\` section with a fenced \`\`\`ignore block showing how users invoke the macro, since a proc macro crate cannot use its own macros in doctests.
+- Be concise. Use a technical tone.
+- Use Rust doc comment syntax with triple slashes (///).
+The full source of the file is in ${fileRef} for reference.`;
+    addTraitItemPrompt(_, declNode);
+    addFfiPrompt(_, declNode);
+    addGenericsPrompt(_, declNode);
+    addCfgPrompt(_, declNode);
+    addRequiredSectionsPrompt(_, declNode);
+    if (this.options.examples) this.addExamplesPrompt(_, declNode, filename);
     return _.$`Generate a Rust documentation comment for the ${declKind} ${declRef}.
 - Make sure parameters, return types, and errors are documented if relevant.
 - Be concise. Use a technical tone.
@@ -124,12 +2038,55 @@ class Rust implements LanguageOps {
 The full source of the file is in ${fileRef} for reference.`;
   }
 
-  addUpdateDocPrompt(_: ChatGenerationContext, declKind: any, declRef: string) {
+  addUpdateDocPrompt(
+    _: ChatGenerationContext,
+    declKind: any,
+    declRef: string,
+    declNode?: SgNode,
+    filename?: string
+  ) {
+    const inlineModule = getInlineModuleDocs(declNode).length > 0;
+    if (declKind === "source_file" || inlineModule) {
+      const itemsRef = _.def(
+        "PUBLIC_ITEMS",
+        getPublicItemsSummary(
+          inlineModule ? declNode.field("body") : declNode
+        ) || "(no public items)"
+      );
+      return _.$`Update the Rust inner documentation comment <DOCSTRING> of ${inlineModule ? `the module ${declRef}` : "the crate or module in <FILE>"}.
+- If the docstring is up to date, return /NO/. It's ok to leave it as is.
+- Do not rephrase an existing sentence if it is correct.
+- Make sure the main public items listed in ${itemsRef} are described.
+- Use Rust inner doc comment syntax (//!), even if <DOCSTRING> uses /*! */ or #![doc = "..."]; it is converted back to the original style.
+- Minimize updates to the existing docstring.
+
+The current docstring is <DOCSTRING>.`;
+    }
+    const macroKind = getMacroKind(declNode);
+    if (macroKind)
+      _.$`The declaration is a ${macroKind}.
+- Make sure the syntax it accepts (each matcher arm, or the input of the proc macro) and the invocation example match the code.`.role(
+        "system"
+      );
+    const tupleField = getTupleFieldText(declNode);
+    if (tupleField)
+      _.$`The declaration is the tuple field \`${tupleField}\` of \`${getPropertyParent(declNode).text()}\`.`.role(
+        "system"
+      );
+    addTraitItemPrompt(_, declNode);
+    addFfiPrompt(_, declNode);
+    addGenericsPrompt(_, declNode);
+    addCfgPrompt(_, declNode);
+    addRequiredSectionsPrompt(_, declNode);
+    if (this.options.examples)
+      _.$`- Keep existing \`# Examples\` sections and their doctests unless they no longer match the code.`.role(
+        "system"
+      );
     return _.$`Update the Rust documentation comment <DOCSTRING> to match the code in ${declKind} ${declRef}.
 - If the docstring is up to date, return /NO/. It's ok to leave it as is.
 - Do not rephrase an existing sentence if it is correct.
 - Make sure parameters, return types, and errors are documented.
-- Use Rust doc comment syntax with triple slashes (///).
+- Use Rust doc comment syntax with triple slashes (///), even if <DOCSTRING> uses /** */ or #[doc = "..."]; it is converted back to the original style.
 - Use standard Rust documentation conventions.
 - Do NOT include types in parameter descriptions, this is for Rust.
 - Minimize updates to the existing docstring.
@@ -0,0 +1,98 @@
+import { type SgNode } from "@genaiscript/plugin-ast-grep";
+
+// Outer attributes (#[derive(...)], #[cfg(...)], ...) sit between an item and
+// its doc comment, so doc lookups need to walk back over them.
+export const attributeKinds = ["attribute_item"];
+export const itemPrefixKinds = [
+  ...attributeKinds,
+  "line_comment",
+  "block_comment",
+];
+
+/**
+ * Collects the run of siblings directly preceding `node` whose kind is one of
+ * `kinds`, in source order.
+ */
+export function getPrecedingSiblings(
+  node: SgNode,
+  kinds: string[]
+): SgNode[] {
+  const nodes: SgNode[] = [];
+  let current = node.prev();
+  while (current && kinds.includes(current.kind())) {
+    nodes.unshift(current);
+    current = current.prev();
+  }
+  return nodes;
+}
+
+// rustdoc accepts three forms of outer docs: `///` lines, `/** */` blocks and
+// `#[doc = "..."]` attributes. `////` and `/***` are regular comments.
+// Inner docs (`//!`, `/*! */`, `#![doc = "..."]`) document the enclosing file.
+export type DocStyle = "line" | "block" | "attribute";
+
+// Node kind and regex (valid for both JavaScript and ast-grep) of the doc
+// comments of each style
+type DocPattern = { kind: string; regex: string };
+export const outerDocPatterns: Record<DocStyle, DocPattern> = {
+  line: { kind: "line_comment", regex: "^///([^/]|$)" },
+  block: { kind: "block_comment", regex: "^/\\*\\*[^*/]" },
+  attribute: { kind: "attribute_item", regex: "^#\\[\\s*doc\\s*=" },
+};
+export const innerDocPatterns: Record<DocStyle, DocPattern> = {
+  line: { kind: "line_comment", regex: "^//!" },
+  block: { kind: "block_comment", regex: "^/\\*!" },
+  attribute: { kind: "inner_attribute_item", regex: "^#!\\[\\s*doc\\s*=" },
+};
+
+/**
+ * Returns the style of a doc comment node, or undefined if the node is not
+ * an outer doc comment (inner if `inner` is set).
+ */
+export function getDocStyle(node: SgNode, inner = false): DocStyle | undefined {
+  const kind = node.kind();
+  const text = node.text();
+  for (const [style, pattern] of Object.entries(
+    inner ? innerDocPatterns : outerDocPatterns
+  ))
+    if (kind === pattern.kind && new RegExp(pattern.regex).test(text))
+      return style as DocStyle;
+  return undefined;
+}
+
+/**
+ * Checks whether an item has outer docs (`///`, `/** */` or `#[doc = ...]`)
+ * above it or between its attributes.
+ */
+export function hasOuterDocs(node: SgNode): boolean {
+  return getPrecedingSiblings(node, itemPrefixKinds).some((n) =>
+    getDocStyle(n)
+  );
+}
+
+// #[macro_export] exports a macro_rules! macro at the root of the crate
+export const macroExportRegex = "^#\\[\\s*macro_export\\b";
+
+/**
+ * Checks whether a `macro_rules!` definition is annotated with
+ * `#[macro_export]`, which exports it at the root of the crate whatever the
+ * module it is declared in.
+ */
+export function isMacroExported(node: SgNode): boolean {
+  const regex = new RegExp(macroExportRegex);
+  return getPrecedingSiblings(node, itemPrefixKinds).some((n) =>
+    regex.test(n.text())
+  );
+}
+
+/**
+ * Returns the self type of an impl block without its generic arguments,
+ * e.g. `geo::Point` for `impl<T> geo::Point<T>`.
+ */
+export function getImplTypeName(node: SgNode): string | undefined {
+  return node
+    .field("type")
+    ?.text()
+    .replace(/<[\s\S]*$/, "")
+    .trim();
+}
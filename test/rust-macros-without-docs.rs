@@ -0,0 +1,63 @@
+#[macro_export]
+macro_rules! square {
+    ($x:expr) => {
+        $x * $x
+    };
+}
+
+macro_rules! max_of {
+    ($a:expr) => {
+        $a
+    };
+    ($a:expr, $($rest:expr),+) => {
+        if $a > max_of!($($rest),+) { $a } else { max_of!($($rest),+) }
+    };
+}
+
+pub mod outer {
+    pub mod inner {
+        pub fn helper() -> u32 {
+            square!(2) + max_of!(1, 2, 3)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        #[test]
+        fn it_works() {
+            assert_eq!(super::inner::helper(), 7);
+        }
+    }
+}
+
+pub mod units {
+    //! Unit conversions.
+
+    pub fn to_feet(meters: f64) -> f64 {
+        meters * 3.28084
+    }
+}
+
+// continuous-comments: ignore-next
+pub mod generated {
+    pub fn table() -> [u8; 4] {
+        [0, 1, 2, 3]
+    }
+}
+
+pub fn version() -> u32 { // continuous-comments: ignore
+    1
+}
+
+pub struct Flags; // continuous-comments: ignore
+pub struct Mode;
+
+#[repr(C)]
+pub union IntOrFloat {
+    pub i: u32,
+    pub f: f32,
+}
+
+extern "C" {
+    pub fn abs(input: i32) -> i32;
+}
@@ -0,0 +1,129 @@
+//! Geometry primitives used by the examples.
+//!
+//! Provides points, colors and a few helpers to compute distances.
+
+use std::fmt::Display;
+
+/// A point in 2D space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// Horizontal coordinate.
+    pub x: f64,
+
+    /// Vertical coordinate.
+    pub y: f64,
+}
+
+/// Colors supported by the renderer.
+// Keep in sync with the palette in assets/palette.toml.
+#[derive(Debug)]
+/// Variants are ordered by hue.
+#[non_exhaustive]
+pub enum Color {
+    /// Pure red.
+    Red,
+    Green,
+
+    /// Pure blue.
+    Blue,
+}
+
+/// The ratio of a circle's circumference to its diameter.
+pub const PI: f64 = 3.14159265359;
+
+
+/// Adds two numbers.
+pub fn add_numbers(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+/**
+ * Computes the euclidean distance between two points.
+ */
+pub fn calculate_distance(p1: &Point, p2: &Point) -> f64 {
+    let dx = p1.x - p2.x;
+    let dy = p1.y - p2.y;
+
+    (dx * dx + dy * dy).sqrt()
+}
+
+impl Point {
+    /// Creates a new point.
+    pub fn new(x: f64, y: f64) -> Self {
+        Point { x, y }
+    }
+
+    /// Returns the origin.
+    pub fn origin() -> Self {
+        Point { x: 0.0, y: 0.0 }
+    }
+
+    /// Moves the point by `dx` horizontally.
+    ///
+    /// # Arguments
+    ///
+    /// * `dx` - Horizontal offset.
+    pub fn translate(&mut self, dx: f64, dy: f64) {
+        self.x += dx;
+        self.y += dy;
+    }
+
+    #[doc = " Reads the x coordinate through a raw pointer."]
+    #[inline]
+    pub fn read_x(ptr: *const Point) -> f64 {
+        // SAFETY: callers pass a pointer to a live `Point`.
+
+        unsafe { (*ptr).x }
+    }
+}
+
+impl Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {})", self.x, self.y)
+    }
+}
+
+/// Shapes
+pub mod shapes {
+    use super::Point;
+
+    /// A circle defined by its center and radius.
+    pub struct Circle {
+        /// Center of the circle.
+        pub center: Point,
+        /// Radius of the circle.
+        pub radius: f64,
+    }
+
+    /// Nested module for polygons.
+    pub mod polygons {
+        /// A triangle.
+        #[derive(Debug)]
+        pub struct Triangle;
+
+
+        /// Number of sides of a triangle.
+        pub const SIDES: usize = 3;
+    }
+}
+
+pub mod units {
+    //! Unit conversions.
+
+    /// Converts meters to feet.
+    pub fn to_feet(meters: f64) -> f64 {
+        meters * 3.28084
+    }
+}
+
+/// Something that can be drawn.
+pub trait Drawable {
+    /// Draws the item.
+    fn draw(&self);
+}
+
+fn main() {
+    let p1 = Point::new(3.0, 4.0);
+    let p2 = Point::origin();
+    println!("Distance: {}", calculate_distance(&p1, &p2));
+}
@@ -12,6 +12,13 @@ pub enum Color {
     Blue,
 }
 
+pub struct Meters(pub f64);
+
+pub struct Span(
+    pub usize,
+    usize,
+);
+
 pub const PI: f64 = 3.14159265359;
 
 pub fn add_numbers(a: i32, b: i32) -> i32 {
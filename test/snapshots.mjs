@@ -0,0 +1,90 @@
+// Offline snapshot tests: runs continuous-comments with mock=true on copies of
+// the fixtures and compares the edited files with the expected outputs in
+// test/snapshots. Run with --update to accept the new outputs.
+import { execFileSync } from "node:child_process";
+import {
+  copyFileSync,
+  existsSync,
+  mkdirSync,
+  readFileSync,
+  rmSync,
+  writeFileSync,
+} from "node:fs";
+import { basename, join } from "node:path";
+
+const cases = [
+  { fixture: "test/rust-without-docs.rs", mode: "write" },
+  {
+    fixture: "test/rust-with-docs.rs",
+    mode: "update",
+    vars: ["updateExisting=true"],
+  },
+  // the stale check keeps most docs of the fixture, turn it off so that the
+  // rewrite of /** */ and #[doc = "..."] docs is covered too
+  {
+    fixture: "test/rust-with-docs.rs",
+    mode: "update-all",
+    vars: ["updateExisting=true", "rustStaleCheck=false"],
+  },
+  { fixture: "test/rust-macros-without-docs.rs", mode: "write" },
+];
+
+const update = process.argv.includes("--update");
+const outputDir = join("test", "output", "snapshots");
+rmSync(outputDir, { recursive: true, force: true });
+mkdirSync(outputDir, { recursive: true });
+
+let failed = 0;
+for (const { fixture, mode, vars = [] } of cases) {
+  const name = basename(fixture).replace(/(\.\w+)$/, `.${mode}$1`);
+  const actualFile = join(outputDir, name);
+  const expectedFile = join("test", "snapshots", `${name}.snap`);
+  copyFileSync(fixture, actualFile);
+  execFileSync(
+    "npx",
+    [
+      "genaiscript",
+      "run",
+      "continuous-comments",
+      actualFile,
+      "--ignore-git-ignore",
+      "--vars",
+      "mock=true",
+      "dryRun=false",
+      ...vars,
+    ],
+    { stdio: "inherit" }
+  );
+
+  const actual = readFileSync(actualFile, "utf8");
+  if (update) {
+    writeFileSync(expectedFile, actual);
+    console.log(`updated ${expectedFile}`);
+    continue;
+  }
+  const expected = existsSync(expectedFile)
+    ? readFileSync(expectedFile, "utf8")
+    : undefined;
+  if (actual === expected) {
+    console.log(`ok ${name}`);
+    continue;
+  }
+  failed++;
+  console.error(`${name} does not match ${expectedFile}`);
+  try {
+    execFileSync(
+      "git",
+      ["--no-pager", "diff", "--no-index", "--", expectedFile, actualFile],
+      { stdio: "inherit" }
+    );
+  } catch {
+    // git diff exits with 1 when the files differ
+  }
+}
+
+if (failed) {
+  console.error(
+    `${failed} snapshot(s) drifted, run \`npm run test:snapshots -- --update\` to accept the changes`
+  );
+  process.exit(1);
+}